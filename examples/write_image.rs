@@ -23,7 +23,7 @@ fn get_section_color(section: u16) -> Pixel {
         section if section < 255 => 0,
         _ => section - 255,
     };
-    return Pixel::new(r as u8, g as u8, b as u8);
+    Pixel::new(r as u8, g as u8, b as u8)
 }
 
 fn main() {
@@ -41,28 +41,28 @@ fn main() {
         .corridor_errantness(0.95)
         .prune_length(64)
         .generate();
-    let mut img = Image::new(map.width as u32, map.height as u32);
+    let mut img = Image::new(map.width, map.height);
 
-    for (cell, x, y) in map.iter_enumerate() {
+    for (x, y, cell) in map.iter() {
         match cell {
             Cell::Room(idx) => img.set_pixel(
-                x.into(),
-                y.into(),
+                x,
+                y,
                 get_section_color(
-                    map.section_vec[map.get_room(idx).section_id].get_id() as u16 * 4,
+                    map.section_vec[map.get_room(*idx).section_id].get_id() as u16 * 4,
                 ),
             ),
             Cell::Corridor(idx) => img.set_pixel(
-                x.into(),
-                y.into(),
+                x,
+                y,
                 get_section_color(
-                    map.section_vec[map.get_corridor(idx).section_id].get_id() as u16 * 32,
+                    map.section_vec[map.get_corridor(*idx).section_id].get_id() as u16 * 32,
                 ),
             ),
-            Cell::Removed => img.set_pixel(x.into(), y.into(), removed_pixel),
-            Cell::Connection => img.set_pixel(x.into(), y.into(), connection_pixel),
-            Cell::SolidRock => img.set_pixel(x.into(), y.into(), solid_wall_pixel),
-            _ => img.set_pixel(x.into(), y.into(), wall_pixel),
+            Cell::Removed => img.set_pixel(x, y, removed_pixel),
+            Cell::Connection => img.set_pixel(x, y, connection_pixel),
+            Cell::SolidRock => img.set_pixel(x, y, solid_wall_pixel),
+            _ => img.set_pixel(x, y, wall_pixel),
         };
     }
     img.save("print.bmp").unwrap();
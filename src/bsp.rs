@@ -0,0 +1,482 @@
+use crate::cell_matrix::{Cell, Map};
+use crate::corridor_tree::CorridorNode;
+use crate::drunkards_walk::{add_tunnels, dig, is_diggable};
+use crate::map_generator::GeneratorOptions;
+use crate::prefab::roll_prefab;
+use crate::room::Room;
+use crate::room_generator::{
+    add_room, room_fits, room_limit_reached, room_size, smallest_room_size,
+};
+
+use rand::Rng;
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bsp {
+    // (width, height), leaves are only split when both halves are at least this big
+    pub min_leaf: (u32, u32),
+    // (min, max) 0-1, where along the leaf the split is made
+    pub split_ratio: (f32, f32),
+    // How many cells are kept free between a room and the edges of its leaf
+    pub padding: u32,
+}
+
+impl Default for Bsp {
+    fn default() -> Self {
+        return Bsp {
+            min_leaf: (12, 12),
+            split_ratio: (0.4, 0.6),
+            padding: 2,
+        };
+    }
+}
+
+impl Bsp {
+    /**
+     * Leaves need a size and the split ratio has to be a range inside 0-1
+     */
+    pub(crate) fn is_valid(&self) -> bool {
+        return self.min_leaf.0 > 0
+            && self.min_leaf.1 > 0
+            && 0f32 <= self.split_ratio.0
+            && self.split_ratio.0 <= self.split_ratio.1
+            && self.split_ratio.1 <= 1f32;
+    }
+}
+
+// A part of the map, only the leaves without children get a room
+struct Leaf {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    children: Option<(usize, usize)>,
+}
+
+/**
+ * Splits the map into leaves and puts a room into every leaf that fits one.
+ * Going up the tree the rooms of sibling leaves are joined with a corridor
+ */
+pub fn partition<R: Rng>(rng: &mut R, map: &mut Map, bsp: &Bsp, options: &GeneratorOptions) {
+    let mut leaves = vec![Leaf {
+        x: 0,
+        y: 0,
+        width: map.width,
+        height: map.height,
+        children: None,
+    }];
+    // A stack as maps can be very big
+    let mut stack = vec![0];
+    while let Some(idx) = stack.pop() {
+        let (x, y, width, height) = {
+            let leaf = &leaves[idx];
+            (leaf.x, leaf.y, leaf.width, leaf.height)
+        };
+        let can_split_x = width >= bsp.min_leaf.0 * 2;
+        let can_split_y = height >= bsp.min_leaf.1 * 2;
+        let vertical = match (can_split_x, can_split_y) {
+            (false, false) => continue,
+            (true, false) => true,
+            (false, true) => false,
+            // Long leaves are split across so the leaves stay close to square
+            _ => {
+                if width * 4 > height * 5 {
+                    true
+                } else if height * 4 > width * 5 {
+                    false
+                } else {
+                    rng.gen()
+                }
+            }
+        };
+        let (size, min) = if vertical {
+            (width, bsp.min_leaf.0)
+        } else {
+            (height, bsp.min_leaf.1)
+        };
+        let ratio = bsp.split_ratio.0 + rng.gen::<f32>() * (bsp.split_ratio.1 - bsp.split_ratio.0);
+        let split = ((size as f32 * ratio) as u32).max(min).min(size - min);
+        let (first, second) = if vertical {
+            ((x, y, split, height), (x + split, y, width - split, height))
+        } else {
+            ((x, y, width, split), (x, y + split, width, height - split))
+        };
+        for (x, y, width, height) in [first, second].iter() {
+            stack.push(leaves.len());
+            leaves.push(Leaf {
+                x: *x,
+                y: *y,
+                width: *width,
+                height: *height,
+                children: None,
+            });
+        }
+        leaves[idx].children = Some((leaves.len() - 2, leaves.len() - 1));
+    }
+
+    let size = (
+        options.corridor_width as u32,
+        options.corridor_height as u32,
+    );
+    // Children always come after their parent, so going backwards the rooms
+    // of both children are known before the parent joins them
+    let mut rooms: Vec<Vec<usize>> = vec![vec![]; leaves.len()];
+    let mut tunnels = vec![false; map.cell_vector.len()];
+    let mut corridor_tree = vec![];
+    // Rooms that are already on the map are joined from the leaf their center is in
+    for room_idx in 0..map.iter_rooms().len() {
+        let (x, y) = room_center(map.get_room(room_idx));
+        let leaf = (0..leaves.len()).find(|idx| {
+            let leaf = &leaves[*idx];
+            leaf.children.is_none()
+                && (leaf.x..leaf.x + leaf.width).contains(&x)
+                && (leaf.y..leaf.y + leaf.height).contains(&y)
+        });
+        match leaf {
+            Some(leaf) => rooms[leaf].push(room_idx),
+            None => {}
+        }
+    }
+    for idx in 0..leaves.len() {
+        if leaves[idx].children.is_none() {
+            if let Some(room) = place_room(rng, map, &leaves[idx], bsp, options) {
+                rooms[idx].push(room);
+            }
+        }
+    }
+    for idx in (0..leaves.len()).rev() {
+        let (first, second) = match leaves[idx].children {
+            Some(children) => children,
+            None => continue,
+        };
+        let mut first_rooms = std::mem::take(&mut rooms[first]);
+        let mut second_rooms = std::mem::take(&mut rooms[second]);
+        if !first_rooms.is_empty() && !second_rooms.is_empty() {
+            // The room closest to the other leaf and the room closest to that one
+            let leaf = &leaves[second];
+            let target = (leaf.x + leaf.width / 2, leaf.y + leaf.height / 2);
+            let from = closest_room(map, &first_rooms, target);
+            let to = closest_room(map, &second_rooms, room_center(map.get_room(from)));
+            let leaf = &leaves[idx];
+            let area = (leaf.x, leaf.y, leaf.width, leaf.height);
+            let path = join_rooms(rng, map, from, to, area, options);
+            // Every piece is a child of the one before it, like the backtracker carves them
+            let mut parent = None;
+            for (x, y) in path {
+                dig(&mut tunnels, map.width, x, y, size);
+                let node = CorridorNode::new(parent.as_ref(), x, y);
+                if parent.is_none() {
+                    corridor_tree.push(Rc::clone(&node));
+                }
+                parent = Some(node);
+            }
+        }
+        first_rooms.append(&mut second_rooms);
+        rooms[idx] = first_rooms;
+    }
+    add_tunnels(map, &tunnels);
+    map.corridor_tree.append(&mut corridor_tree);
+}
+
+/**
+ * Tries a few sizes and positions for a room inside of the padding of the leaf
+ */
+fn place_room<R: Rng>(
+    rng: &mut R,
+    map: &mut Map,
+    leaf: &Leaf,
+    bsp: &Bsp,
+    options: &GeneratorOptions,
+) -> Option<usize> {
+    if room_limit_reached(map, options) {
+        return None;
+    }
+    let space = (
+        leaf.width.saturating_sub(bsp.padding * 2),
+        leaf.height.saturating_sub(bsp.padding * 2),
+    );
+    let smallest = smallest_room_size(options);
+    if space.0 < smallest.0 || space.1 < smallest.1 {
+        return None;
+    }
+    let room_shapes = &options.room_shapes;
+    for _ in 0..4 {
+        let room = match roll_prefab(rng, options) {
+            Some(mut room) if room.width <= space.0 && room.height <= space.1 => {
+                room.x = leaf.x + bsp.padding + rng.gen_range(0, space.0 - room.width + 1);
+                room.y = leaf.y + bsp.padding + rng.gen_range(0, space.1 - room.height + 1);
+                room
+            }
+            _ => {
+                let room_shape = room_shapes[rng.gen_range(0, room_shapes.len())];
+                let (room_width, room_height) = room_size(rng, options);
+                let (room_width, room_height) = (room_width.min(space.0), room_height.min(space.1));
+                let x = leaf.x + bsp.padding + rng.gen_range(0, space.0 - room_width + 1);
+                let y = leaf.y + bsp.padding + rng.gen_range(0, space.1 - room_height + 1);
+                Room::new(rng, x, y, room_width, room_height, room_shape, 0)
+            }
+        };
+        if room_fits(map, &room, options) {
+            add_room(map, room, options);
+            return Some(map.iter_rooms().len() - 1);
+        }
+    }
+    return None;
+}
+
+fn room_center(room: &Room) -> (u32, u32) {
+    return (room.x + room.width / 2, room.y + room.height / 2);
+}
+
+fn closest_room(map: &Map, rooms: &[usize], target: (u32, u32)) -> usize {
+    return *rooms
+        .iter()
+        .min_by_key(|idx| {
+            let (x, y) = room_center(map.get_room(**idx));
+            let (d_x, d_y) = (x as i64 - target.0 as i64, y as i64 - target.1 as i64);
+            d_x * d_x + d_y * d_y
+        })
+        .unwrap();
+}
+
+/**
+ * The corridor pieces between the rooms, an L from the center of one room
+ * to the center of the other when nothing is in the way, otherwise the
+ * shortest way around inside of the area. The corridor ends the margins
+ * away from the rooms, where the section merger puts doors across. Empty
+ * if there's no way inside of the area
+ */
+fn join_rooms<R: Rng>(
+    rng: &mut R,
+    map: &Map,
+    from: usize,
+    to: usize,
+    area: (u32, u32, u32, u32),
+    options: &GeneratorOptions,
+) -> Vec<(u32, u32)> {
+    let size = (
+        options.corridor_width as u32,
+        options.corridor_height as u32,
+    );
+    let piece_position = |(x, y): (u32, u32)| {
+        return (x.saturating_sub(size.0 / 2), y.saturating_sub(size.1 / 2));
+    };
+    let start = piece_position(room_center(map.get_room(from)));
+    let end = piece_position(room_center(map.get_room(to)));
+    let corners = if rng.gen() {
+        [(end.0, start.1), (start.0, end.1)]
+    } else {
+        [(start.0, end.1), (end.0, start.1)]
+    };
+    let diggable = |(x, y): (u32, u32)| is_diggable(map, x, y, size, options.margins);
+    for corner in corners.iter() {
+        let mut path = straight_path(start, *corner);
+        path.pop();
+        path.append(&mut straight_path(*corner, end));
+        // The ends inside of the rooms and their margins aren't dug
+        let first = path.iter().position(|piece| diggable(*piece));
+        let last = path.iter().rposition(|piece| diggable(*piece));
+        if let (Some(first), Some(last)) = (first, last) {
+            let path = &path[first..=last];
+            if path.iter().all(|piece| diggable(*piece))
+                && is_next_to_room(map, path[0], from, options)
+                && is_next_to_room(map, path[path.len() - 1], to, options)
+            {
+                return path.to_vec();
+            }
+        }
+    }
+    return route_around(map, from, to, area, options);
+}
+
+/**
+ * Every position from one position to the other along a straight line
+ */
+fn straight_path(from: (u32, u32), to: (u32, u32)) -> Vec<(u32, u32)> {
+    let mut path = vec![from];
+    let (mut x, mut y) = from;
+    while (x, y) != to {
+        if x != to.0 {
+            x = if x < to.0 { x + 1 } else { x - 1 };
+        } else {
+            y = if y < to.1 { y + 1 } else { y - 1 };
+        }
+        path.push((x, y));
+    }
+    return path;
+}
+
+/**
+ * Whether a corridor piece is straight across the margins from the room
+ */
+fn is_next_to_room(map: &Map, (x, y): (u32, u32), room: usize, options: &GeneratorOptions) -> bool {
+    let (x, y) = (x as i32, y as i32);
+    let size = (
+        options.corridor_width as u32,
+        options.corridor_height as u32,
+    );
+    let margins = (options.margins.0 as u32, options.margins.1 as u32);
+    let room = Cell::Room(room);
+    return map
+        .rect_is(
+            x - margins.0 as i32 - 1,
+            y,
+            size.0 + 2 * margins.0 + 2,
+            size.1,
+            |c| *c == room,
+        )
+        .is_some()
+        || map
+            .rect_is(
+                x,
+                y - margins.1 as i32 - 1,
+                size.0,
+                size.1 + 2 * margins.1 + 2,
+                |c| *c == room,
+            )
+            .is_some();
+}
+
+/**
+ * Shortest corridor from a piece next to one room to a piece next to the
+ * other, going around what's in the way. Only searches inside of the area
+ * so that joining stays fast on big maps
+ */
+fn route_around(
+    map: &Map,
+    from: usize,
+    to: usize,
+    area: (u32, u32, u32, u32),
+    options: &GeneratorOptions,
+) -> Vec<(u32, u32)> {
+    let size = (
+        options.corridor_width as u32,
+        options.corridor_height as u32,
+    );
+    let (area_x, area_y, width, height) = area;
+    let local = |x: u32, y: u32| ((y - area_y) * width + (x - area_x)) as usize;
+    // The piece each piece was reached from, the start pieces point to themselves
+    let mut reached_from: Vec<Option<(u32, u32)>> = vec![None; (width * height) as usize];
+    let mut queue = VecDeque::new();
+    for y in area_y..area_y + height {
+        for x in area_x..area_x + width {
+            if is_diggable(map, x, y, size, options.margins)
+                && is_next_to_room(map, (x, y), from, options)
+            {
+                reached_from[local(x, y)] = Some((x, y));
+                queue.push_back((x, y));
+            }
+        }
+    }
+    while let Some((x, y)) = queue.pop_front() {
+        if is_next_to_room(map, (x, y), to, options) {
+            let mut path = vec![(x, y)];
+            while let Some(previous) =
+                reached_from[local(path[path.len() - 1].0, path[path.len() - 1].1)]
+            {
+                if previous == path[path.len() - 1] {
+                    break;
+                }
+                path.push(previous);
+            }
+            path.reverse();
+            return path;
+        }
+        let neighbours = [
+            (x, y.wrapping_sub(1)),
+            (x + 1, y),
+            (x, y + 1),
+            (x.wrapping_sub(1), y),
+        ];
+        for (n_x, n_y) in neighbours.iter() {
+            let (n_x, n_y) = (*n_x, *n_y);
+            if !(area_x..area_x + width).contains(&n_x)
+                || !(area_y..area_y + height).contains(&n_y)
+                || reached_from[local(n_x, n_y)].is_some()
+                || !is_diggable(map, n_x, n_y, size, options.margins)
+            {
+                continue;
+            }
+            reached_from[local(n_x, n_y)] = Some((x, y));
+            queue.push_back((n_x, n_y));
+        }
+    }
+    return vec![];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cave::regions;
+    use crate::error::GenerateError;
+    use crate::map_generator::{Generator, RoomPlacement};
+
+    /**
+     * A map with its rooms placed in the leaves of a partition
+     */
+    fn partitioned(seed: u64, bsp: Bsp) -> Result<Map, GenerateError> {
+        return Generator::new()
+            .seed(seed)
+            .room_placement(RoomPlacement::Bsp(bsp))
+            .try_generate();
+    }
+
+    #[test]
+    fn rooms_are_apart_and_inside_of_the_map() {
+        for seed in 0..10 {
+            let map = partitioned(seed, Bsp::default()).unwrap();
+            let rooms: Vec<&Room> = map.iter_rooms().collect();
+            assert!(rooms.len() > 1, "seed {}", seed);
+            for (idx, room) in rooms.iter().enumerate() {
+                assert!(room.x + room.width <= map.width && room.y + room.height <= map.height);
+                for other in rooms.iter().skip(idx + 1) {
+                    let apart = room.x + room.width <= other.x
+                        || other.x + other.width <= room.x
+                        || room.y + room.height <= other.y
+                        || other.y + other.height <= room.y;
+                    assert!(apart, "seed {}", seed);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn partitioned_maps_are_one_region() {
+        for seed in 0..10 {
+            let map = partitioned(seed, Bsp::default()).unwrap();
+            assert!(!map.corridor_tree.is_empty());
+            let rock: Vec<bool> = map.cell_vector.iter().map(|c| !c.is_walkable()).collect();
+            let size = (map.width as i32, map.height as i32);
+            assert_eq!(regions(&rock, size).len(), 1, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn invalid_partitions() {
+        let bsps = [
+            Bsp {
+                min_leaf: (0, 12),
+                ..Bsp::default()
+            },
+            Bsp {
+                split_ratio: (0.6, 0.4),
+                ..Bsp::default()
+            },
+            Bsp {
+                split_ratio: (0.5, 1.5),
+                ..Bsp::default()
+            },
+        ];
+        for bsp in bsps.iter() {
+            assert_eq!(
+                partitioned(0, *bsp).err(),
+                Some(GenerateError::InvalidBsp {
+                    min_leaf: bsp.min_leaf,
+                    split_ratio: bsp.split_ratio,
+                })
+            );
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use crate::cell_matrix::{Cell, Map};
+use crate::room::{Room, RoomShape};
+
+use rand::Rng;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cave {
+    // 0-1, share of the cells that start as rock
+    pub fill: f32,
+    // Floor turns into rock with this many rock neighbours
+    pub birth: Vec<u8>,
+    // Rock stays rock with this many rock neighbours
+    pub survival: Vec<u8>,
+    // How many smoothing steps are run
+    pub iterations: u32,
+    // Caverns with fewer cells are filled with rock
+    pub min_size: u32,
+}
+
+impl Default for Cave {
+    fn default() -> Self {
+        return Cave {
+            fill: 0.45,
+            birth: vec![5, 6, 7, 8],
+            survival: vec![4, 5, 6, 7, 8],
+            iterations: 5,
+            min_size: 16,
+        };
+    }
+}
+
+/**
+ * Grows caverns in the rock of the map with a cellular automaton, every
+ * cavern becomes a room with its own section
+ */
+pub fn generate_caves<R: Rng>(rng: &mut R, map: &mut Map, cave: &Cave) {
+    let (width, height) = (map.width as i32, map.height as i32);
+    let map_width = map.width;
+    // Cells on the edge of the map or next to the outside of the map shape
+    // are always rock, so caverns get walls all around
+    let fixed: Vec<bool> = map
+        .iter()
+        .map(|(x, y, cell)| {
+            !cell.is_rock()
+                || x == 0
+                || y == 0
+                || x == map.width - 1
+                || y == map.height - 1
+                || map.neighbors8(x, y).any(|(_, _, n)| *n == Cell::SolidRock)
+        })
+        .collect();
+    let mut rock: Vec<bool> = fixed
+        .iter()
+        .map(|fixed| *fixed || rng.gen::<f32>() < cave.fill)
+        .collect();
+
+    smooth(
+        &mut rock,
+        &fixed,
+        (width, height),
+        &cave.birth,
+        &cave.survival,
+        cave.iterations,
+    );
+
+    for cells in regions(&rock, (width, height)) {
+        if cells.len() < cave.min_size as usize {
+            continue;
+        }
+        let positions = || {
+            cells
+                .iter()
+                .map(|idx| (*idx as u32 % map_width, *idx as u32 / map_width))
+        };
+        let min_x = positions().map(|(x, _)| x).min().unwrap();
+        let max_x = positions().map(|(x, _)| x).max().unwrap();
+        let min_y = positions().map(|(_, y)| y).min().unwrap();
+        let max_y = positions().map(|(_, y)| y).max().unwrap();
+        let (room_width, room_height) = (max_x - min_x + 1, max_y - min_y + 1);
+        let mut footprint = vec![false; (room_width * room_height) as usize];
+        for (x, y) in positions() {
+            footprint[((y - min_y) * room_width + (x - min_x)) as usize] = true;
+        }
+
+        let section_id = map.new_section();
+        let room = Room::from_footprint(
+            min_x,
+            min_y,
+            room_width,
+            room_height,
+            RoomShape::Cave,
+            footprint,
+            section_id,
+        );
+        let idx = map.push_room(room);
+        for (x, y) in positions() {
+            map.set(x, y, Cell::Room(idx));
+        }
+    }
+}
+
+/**
+ * Runs the cellular automaton on the rock, cells outside of the grid
+ * count as rock and fixed cells are left as they are
+ */
+pub(crate) fn smooth(
+    rock: &mut Vec<bool>,
+    fixed: &[bool],
+    size: (i32, i32),
+    birth: &[u8],
+    survival: &[u8],
+    iterations: u32,
+) {
+    let (width, height) = size;
+    for _ in 0..iterations {
+        let mut next = rock.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let idx = (y * width + x) as usize;
+                if fixed[idx] {
+                    continue;
+                }
+                let mut neighbours = 0;
+                for n_y in (y - 1)..=(y + 1) {
+                    for n_x in (x - 1)..=(x + 1) {
+                        if (n_x != x || n_y != y)
+                            && (n_x < 0
+                                || n_y < 0
+                                || n_x >= width
+                                || n_y >= height
+                                || rock[(n_y * width + n_x) as usize])
+                        {
+                            neighbours += 1;
+                        }
+                    }
+                }
+                next[idx] = if rock[idx] {
+                    survival.contains(&neighbours)
+                } else {
+                    birth.contains(&neighbours)
+                };
+            }
+        }
+        *rock = next;
+    }
+}
+
+/**
+ * The groups of floor cells that can reach each other, in the order of
+ * their first cell
+ */
+pub(crate) fn regions(rock: &[bool], size: (i32, i32)) -> Vec<Vec<usize>> {
+    let (width, height) = size;
+    let mut region = vec![false; rock.len()];
+    let mut regions = vec![];
+    for start in 0..rock.len() {
+        if rock[start] || region[start] {
+            continue;
+        }
+        let mut cells = vec![];
+        let mut stack = vec![start];
+        region[start] = true;
+        while let Some(idx) = stack.pop() {
+            cells.push(idx);
+            let (x, y) = ((idx as i32) % width, (idx as i32) / width);
+            for (n_x, n_y) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)].iter() {
+                if *n_x < 0 || *n_y < 0 || *n_x >= width || *n_y >= height {
+                    continue;
+                }
+                let n_idx = (n_y * width + n_x) as usize;
+                if !rock[n_idx] && !region[n_idx] {
+                    region[n_idx] = true;
+                    stack.push(n_idx);
+                }
+            }
+        }
+        regions.push(cells);
+    }
+    return regions;
+}
+
+/**
+ * The cells of the largest group of floor cells, the first one if
+ * there are several
+ */
+pub(crate) fn largest_region(rock: &[bool], size: (i32, i32)) -> Vec<usize> {
+    let mut largest: Vec<usize> = vec![];
+    for cells in regions(rock, size) {
+        if cells.len() > largest.len() {
+            largest = cells;
+        }
+    }
+    return largest;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_generator::{Generator, Layout};
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn caverns_are_cave_rooms() {
+        let cave = Cave {
+            min_size: 24,
+            ..Cave::default()
+        };
+        for seed in 0..10 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut map = Map::new(60, 40, Cell::Rock);
+            generate_caves(&mut rng, &mut map, &cave);
+            assert!(map.iter_rooms().len() > 0, "seed {}", seed);
+            for (idx, room) in map.iter_rooms().enumerate() {
+                assert!(room.shape == RoomShape::Cave);
+                let cells = map
+                    .iter()
+                    .filter(|(_, _, c)| **c == Cell::Room(idx))
+                    .count();
+                assert_eq!(cells, room.cells().count());
+                assert!(cells >= 24, "seed {}", seed);
+            }
+            for (x, y, cell) in map.iter() {
+                if x == 0 || y == 0 || x == 59 || y == 39 {
+                    assert!(cell.is_rock());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn regions_split_on_rock() {
+        // Two floor cells apart and one that only touches them diagonally
+        let rock = vec![
+            false, true, false, //
+            true, false, true, //
+        ];
+        let regions = regions(&rock, (3, 2));
+        assert_eq!(regions, vec![vec![0], vec![2], vec![4]]);
+    }
+
+    #[test]
+    fn cave_maps_are_one_region() {
+        for seed in 0..10 {
+            let map = Generator::new()
+                .seed(seed)
+                .layout(Layout::Cave(Cave::default()))
+                .generate();
+            let rock: Vec<bool> = map.cell_vector.iter().map(|c| !c.is_walkable()).collect();
+            let size = (map.width as i32, map.height as i32);
+            assert_eq!(regions(&rock, size).len(), 1, "seed {}", seed);
+        }
+    }
+}
@@ -1,18 +1,23 @@
 use crate::corridor_tree::WrappedCorridorNode;
-use crate::direction::Direction;
+use crate::direction::Orientation;
+use crate::doors::DoorKind;
 use crate::room::{Corridor, Room};
 use crate::sections::{Connection, Section};
+use crate::walls::WallCorner;
 
 #[derive(Clone, PartialEq, Copy)]
 pub enum Cell {
     Rock,      // Rocks are the ceiling of the map
     SolidRock, // Solid rock are unbreakable rocks, e.g outside of the map
-    Wall,      // horizontal, vertical
+    Wall(Orientation, WallCorner),
     // Room contains the index of the room in the room vector.
     Room(usize),
     Corridor(usize),
-    Perimeter(u8), // how close it is to a wall
+    Perimeter(u8), // how many cells away from a wall it is
     Connection,
+    Door(Orientation, DoorKind),
+    // A hidden passage, see `Map::secret_connections`
+    SecretPassage,
     Removed, // Debug cell
 }
 
@@ -35,11 +40,24 @@ impl Cell {
             _ => false,
         }
     }
+    pub fn is_connection(&self) -> bool {
+        match self {
+            Cell::Connection => true,
+            _ => false,
+        }
+    }
+    pub fn is_wall(&self) -> bool {
+        match self {
+            Cell::Wall(_, _) => true,
+            _ => false,
+        }
+    }
     pub fn is_walkable(&self) -> bool {
         match self {
             Cell::Room(_) => true,
             Cell::Corridor(_) => true,
             Cell::Connection => true,
+            Cell::Door(_, _) => true,
             _ => false,
         }
     }
@@ -50,11 +68,13 @@ impl std::fmt::Display for Cell {
         match self {
             Cell::Rock => write!(f, "Rock"),
             Cell::SolidRock => write!(f, "SolidRock"),
-            Cell::Wall => write!(f, "Wall"),
+            Cell::Wall(_, _) => write!(f, "Wall"),
             Cell::Room(_) => write!(f, "Room"),
             Cell::Corridor(_) => write!(f, "Corridor"),
             Cell::Perimeter(_) => write!(f, "Perimeter"),
             Cell::Connection => write!(f, "Connection"),
+            Cell::Door(_, _) => write!(f, "Door"),
+            Cell::SecretPassage => write!(f, "SecretPassage"),
             Cell::Removed => write!(f, "Removed"),
         }
     }
@@ -62,35 +82,111 @@ impl std::fmt::Display for Cell {
 
 pub struct Map {
     pub cell_vector: Vec<Cell>,
-    pub width: u16,
-    pub height: u16,
+    pub width: u32,
+    pub height: u32,
     room_vec: Vec<Room>,
     corridor_vec: Vec<Corridor>,
     pub corridor_tree: Vec<WrappedCorridorNode>,
     pub section_vec: Vec<Section>,
+    // Unused connections that were turned into secret passages
+    pub secret_connections: Vec<Connection>,
+    // The seed the map was generated from, None if it was generated
+    // with a user supplied rng
+    pub seed: Option<u64>,
 }
 
 const EMPTY_CELL: Cell = Cell::SolidRock;
+const NEIGHBORS4: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+const NEIGHBORS8: [(i32, i32); 8] = [
+    (0, -1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+];
 
 impl Map {
-    pub fn new(width: u16, height: u16, filler_cell: Cell) -> Self {
+    pub fn new(width: u32, height: u32, filler_cell: Cell) -> Self {
         return Map {
             width,
             height,
-            cell_vector: vec![filler_cell; ((width as u32) * (height as u32)) as usize],
+            cell_vector: vec![filler_cell; width as usize * height as usize],
             room_vec: vec![],
             corridor_vec: vec![],
             section_vec: vec![],
             corridor_tree: vec![],
+            secret_connections: vec![],
+            seed: None,
         };
     }
-    pub fn iter_enumerate(&self) -> Vec<(Cell, u16, u16)> {
+    /**
+     * Copies every cell with its position, use `iter` to go through the
+     * cells while the map is not being changed
+     */
+    #[deprecated(note = "use `Map::iter`, which doesn't copy the map")]
+    pub fn iter_enumerate(&self) -> Vec<(Cell, u32, u32)> {
+        return self.iter().map(|(x, y, cell)| (*cell, x, y)).collect();
+    }
+    /**
+     * Iterates over every cell with its position, row by row
+     */
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u32, &Cell)> + '_ {
+        let width = self.width as usize;
         return self
             .cell_vector
             .iter()
             .enumerate()
-            .map(|(i, c)| (c.clone(), i as u16 % self.width, i as u16 / self.width))
-            .collect();
+            .map(move |(i, cell)| ((i % width) as u32, (i / width) as u32, cell));
+    }
+    /**
+     * Iterates mutably over every cell with its position, row by row
+     */
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (u32, u32, &mut Cell)> + '_ {
+        let width = self.width as usize;
+        return self
+            .cell_vector
+            .iter_mut()
+            .enumerate()
+            .map(move |(i, cell)| ((i % width) as u32, (i / width) as u32, cell));
+    }
+    /**
+     * Iterates over the rows of the map from top to bottom
+     */
+    pub fn rows(&self) -> impl Iterator<Item = &[Cell]> + '_ {
+        return self.cell_vector.chunks(self.width.max(1) as usize);
+    }
+    /**
+     * The cells north, east, south and west of the position that are
+     * inside of the map, with their positions
+     */
+    pub fn neighbors4(&self, x: u32, y: u32) -> impl Iterator<Item = (u32, u32, &Cell)> + '_ {
+        return self.neighbors(x, y, &NEIGHBORS4);
+    }
+    /**
+     * The cells around the position that are inside of the map, including
+     * the diagonal ones, with their positions
+     */
+    pub fn neighbors8(&self, x: u32, y: u32) -> impl Iterator<Item = (u32, u32, &Cell)> + '_ {
+        return self.neighbors(x, y, &NEIGHBORS8);
+    }
+    fn neighbors(
+        &self,
+        x: u32,
+        y: u32,
+        offsets: &'static [(i32, i32)],
+    ) -> impl Iterator<Item = (u32, u32, &Cell)> + '_ {
+        return offsets.iter().filter_map(move |(d_x, d_y)| {
+            let n_x = x as i32 + d_x;
+            let n_y = y as i32 + d_y;
+            if n_x < 0 || n_y < 0 || n_x >= self.width as i32 || n_y >= self.height as i32 {
+                return None;
+            }
+            let idx = n_y as usize * self.width as usize + n_x as usize;
+            return Some((n_x as u32, n_y as u32, &self.cell_vector[idx]));
+        });
     }
     pub fn new_section(&mut self) -> usize {
         let index = self.section_vec.len();
@@ -104,7 +200,7 @@ impl Map {
         if x > (self.width as i32 - 1) || y > (self.height as i32 - 1) || x < 0 || y < 0 {
             return &EMPTY_CELL;
         }
-        return &self.cell_vector[(y * (self.width as i32) + x) as usize];
+        return &self.cell_vector[y as usize * self.width as usize + x as usize];
     }
     pub fn get_best_connections(&self, section: &Section) -> Vec<Connection> {
         let mut best_connections: Vec<Connection> = vec![];
@@ -116,7 +212,7 @@ impl Map {
                     == self.get_connection_section(connection).get_id()
                     && c.direction == connection.direction
             }) {
-                best_connections.push(connection.clone());
+                best_connections.push(*connection);
             }
         }
         return best_connections;
@@ -144,7 +240,7 @@ impl Map {
         let cell = self.get(x, y);
         return self.get_cell_section(cell);
     }
-    pub fn get_section_mut(&mut self, x: i32, y: i32) -> Option<(&mut Section)> {
+    pub fn get_section_mut(&mut self, x: i32, y: i32) -> Option<&mut Section> {
         match self.get(x, y) {
             Cell::Room(idx) => {
                 let section_id = self.get_room(*idx).section_id;
@@ -178,31 +274,61 @@ impl Map {
         self.corridor_vec.push(Corridor { section_id });
         return self.corridor_vec.len() - 1;
     }
-    pub fn iter_rooms(&self) -> std::slice::Iter<Room> {
+    pub fn push_corridor(&mut self, corridor: Corridor) -> usize {
+        self.corridor_vec.push(corridor);
+        return self.corridor_vec.len() - 1;
+    }
+    /**
+     * Takes the flagged rooms and corridors out of the map, their cells
+     * have to be gone already. The cells of the rooms and corridors that
+     * are kept are renumbered, their sections stay where they are
+     */
+    pub(crate) fn remove_rooms(&mut self, rooms: &[bool], corridors: &[bool]) {
+        let room_indices = kept_indices(rooms);
+        let corridor_indices = kept_indices(corridors);
+        for cell in self.cell_vector.iter_mut() {
+            match cell {
+                Cell::Room(idx) => *idx = room_indices[*idx],
+                Cell::Corridor(idx) => *idx = corridor_indices[*idx],
+                _ => {}
+            }
+        }
+        let mut idx = 0;
+        self.room_vec.retain(|_| {
+            idx += 1;
+            return !rooms[idx - 1];
+        });
+        let mut idx = 0;
+        self.corridor_vec.retain(|_| {
+            idx += 1;
+            return !corridors[idx - 1];
+        });
+    }
+    pub fn iter_rooms(&self) -> std::slice::Iter<'_, Room> {
         return self.room_vec.iter();
     }
-    pub fn iter_corridors(&self) -> std::slice::Iter<Corridor> {
+    pub fn iter_corridors(&self) -> std::slice::Iter<'_, Corridor> {
         return self.corridor_vec.iter();
     }
-    pub fn get_rect(&self, x: i32, y: i32, width: u16, height: u16) -> Map {
+    pub fn get_rect(&self, x: i32, y: i32, width: u32, height: u32) -> Map {
         let mut cell_matrix_rect = Map::new(width, height, Cell::SolidRock);
         for pos_y in y..(y + height as i32) {
             for pos_x in x..(x + width as i32) {
-                let cell = self.get(pos_x, pos_y).clone();
-                cell_matrix_rect.set((pos_x - x) as u16, (pos_y - y) as u16, cell)
+                let cell = *self.get(pos_x, pos_y);
+                cell_matrix_rect.set((pos_x - x) as u32, (pos_y - y) as u32, cell)
             }
         }
         return cell_matrix_rect;
     }
 
-    pub fn rect_is<F>(&self, x: i32, y: i32, width: u16, height: u16, func: F) -> Option<Cell>
+    pub fn rect_is<F>(&self, x: i32, y: i32, width: u32, height: u32, func: F) -> Option<Cell>
     where
         F: Fn(&Cell) -> bool,
     {
         for pos_y in y..(y + height as i32) {
             for pos_x in x..(x + width as i32) {
-                if func(&self.get(pos_x, pos_y)) {
-                    return Some(*self.get(pos_y, pos_y));
+                if func(self.get(pos_x, pos_y)) {
+                    return Some(*self.get(pos_x, pos_y));
                 }
             }
         }
@@ -214,7 +340,7 @@ impl Map {
         F: Fn(&Cell) -> Option<T>,
     {
         for coord in coords {
-            match func(&self.get(coord.0, coord.1)) {
+            match func(self.get(coord.0, coord.1)) {
                 Some(a) => return Some(a),
                 _ => {}
             }
@@ -226,29 +352,29 @@ impl Map {
         &self,
         x: i32,
         y: i32,
-        width: u16,
-        height: u16,
+        width: u32,
+        height: u32,
         func: F,
     ) -> Option<T>
     where
         F: Fn(&Cell) -> Option<T>,
     {
         for pos_y in y..(y + height as i32) {
-            match func(&self.get(x, pos_y)) {
+            match func(self.get(x, pos_y)) {
                 Some(a) => return Some(a),
                 _ => {}
             }
-            match func(&self.get(x + width as i32, pos_y)) {
+            match func(self.get(x + width as i32, pos_y)) {
                 Some(a) => return Some(a),
                 _ => {}
             }
         }
         for pos_x in x..(x + width as i32) {
-            match func(&self.get(pos_x, y)) {
+            match func(self.get(pos_x, y)) {
                 Some(a) => return Some(a),
                 _ => {}
             }
-            match func(&self.get(pos_x, y + height as i32)) {
+            match func(self.get(pos_x, y + height as i32)) {
                 Some(a) => return Some(a),
                 _ => {}
             }
@@ -256,7 +382,7 @@ impl Map {
         return None;
     }
 
-    pub fn set_rect(&mut self, cell: Cell, x: u16, y: u16, width: u16, height: u16) {
+    pub fn set_rect(&mut self, cell: Cell, x: u32, y: u32, width: u32, height: u32) {
         for pos_y in y..(y + height) {
             for pos_x in x..(x + width) {
                 self.set(pos_x, pos_y, cell)
@@ -264,7 +390,7 @@ impl Map {
         }
     }
 
-    pub fn set(&mut self, x: u16, y: u16, cell: Cell) {
+    pub fn set(&mut self, x: u32, y: u32, cell: Cell) {
         assert!(
             x < self.width,
             "x value not within width of matrix, {}>={}",
@@ -277,25 +403,124 @@ impl Map {
             y,
             self.height
         );
-        self.cell_vector[(y * self.width + x) as usize] = cell;
+        self.cell_vector[y as usize * self.width as usize + x as usize] = cell;
+    }
+}
+
+impl std::ops::Index<(u32, u32)> for Map {
+    type Output = Cell;
+
+    fn index(&self, (x, y): (u32, u32)) -> &Cell {
+        assert!(
+            x < self.width && y < self.height,
+            "position ({}, {}) not within the map of size {}x{}",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+        return &self.cell_vector[y as usize * self.width as usize + x as usize];
     }
 }
 
+/**
+ * The new index of every entry once the flagged entries are taken out
+ */
+fn kept_indices(removed: &[bool]) -> Vec<usize> {
+    let mut kept = 0;
+    return removed
+        .iter()
+        .map(|removed| {
+            let idx = kept;
+            if !removed {
+                kept += 1;
+            }
+            return idx;
+        })
+        .collect();
+}
+
 impl std::fmt::Display for Map {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         // Print the map beautifully
-        let mut map_string = String::with_capacity(self.width as usize + self.height as usize);
-        for (cell, x, y) in self.iter_enumerate() {
-            if x == 0 && y > 0 {
+        let mut map_string =
+            String::with_capacity((self.width as usize + 1) * self.height as usize);
+        for (y, row) in self.rows().enumerate() {
+            if y > 0 {
                 map_string.push('\n');
             }
-            match cell {
-                Cell::Room(_) => map_string.push('R'),
-                Cell::Corridor(_) => map_string.push('C'),
-                Cell::Wall => map_string.push('W'),
-                _ => map_string.push(' '),
-            };
+            for cell in row {
+                match cell {
+                    Cell::Room(_) => map_string.push('R'),
+                    Cell::Corridor(_) => map_string.push('C'),
+                    Cell::Wall(_, _) => map_string.push('W'),
+                    Cell::Door(_, _) => map_string.push('D'),
+                    Cell::SecretPassage => map_string.push('S'),
+                    _ => map_string.push(' '),
+                };
+            }
         }
         return write!(f, "{}", map_string);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * A 4 by 3 map with a room cell in every column on a different row
+     */
+    fn small_map() -> Map {
+        let mut map = Map::new(4, 3, Cell::Rock);
+        for x in 0..4 {
+            map.set(x, x % 3, Cell::Room(x as usize));
+        }
+        return map;
+    }
+
+    #[test]
+    fn iter_matches_get_and_index() {
+        let map = small_map();
+        assert_eq!(map.iter().count(), 12);
+        for (x, y, cell) in map.iter() {
+            assert!(cell == map.get(x as i32, y as i32));
+            assert!(*cell == map[(x, y)]);
+        }
+        assert!(map[(3, 0)] == Cell::Room(3));
+    }
+
+    #[test]
+    fn rows_go_from_top_to_bottom() {
+        let map = small_map();
+        let rows: Vec<&[Cell]> = map.rows().collect();
+        assert_eq!(rows.len(), 3);
+        for (y, row) in rows.iter().enumerate() {
+            assert_eq!(row.len(), 4);
+            for (x, cell) in row.iter().enumerate() {
+                assert!(*cell == map[(x as u32, y as u32)]);
+            }
+        }
+    }
+
+    #[test]
+    fn neighbors_stay_within_the_map() {
+        let map = small_map();
+        assert_eq!(map.neighbors4(0, 0).count(), 2);
+        assert_eq!(map.neighbors8(0, 0).count(), 3);
+        assert_eq!(map.neighbors4(1, 1).count(), 4);
+        assert_eq!(map.neighbors8(1, 1).count(), 8);
+        assert_eq!(map.neighbors8(3, 2).count(), 3);
+        for (x, y, cell) in map.neighbors8(1, 1) {
+            assert!((x as i32 - 1).abs() <= 1 && (y as i32 - 1).abs() <= 1);
+            assert!((x, y) != (1, 1));
+            assert!(*cell == map[(x, y)]);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn index_outside_of_the_map() {
+        let _ = small_map()[(4, 0)];
+    }
+}
@@ -0,0 +1,560 @@
+use crate::cell_matrix::{Cell, Map};
+use crate::dead_ends::detach_removed_nodes;
+use crate::error::GenerateError;
+
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DisconnectedPolicy {
+    // Carves a corridor from the main section to every unreachable part,
+    // parts that can't be reached through rock are removed
+    Reconnect,
+    // Turns every unreachable part back into rock and takes its rooms
+    // out of the map, fixed rooms are still reconnected
+    Remove,
+}
+
+/**
+ * Makes every walkable cell reachable from the section with the main id,
+ * sections that are reached are merged into the main section. Returns an
+ * error if a fixed room can't be reached
+ */
+pub fn ensure_connected(
+    map: &mut Map,
+    main_id: usize,
+    corridor_size: (u32, u32),
+    policy: DisconnectedPolicy,
+) -> Result<(), GenerateError> {
+    let (components, count) = label_components(map);
+    if count <= 1 {
+        return Ok(());
+    }
+    let mut joined = vec![false; count + 1];
+    joined[find_main_component(map, &components, main_id)] = true;
+    // Groups with a fixed room are always reconnected
+    let mut targets = vec![policy == DisconnectedPolicy::Reconnect; count + 1];
+    for (idx, component) in components.iter().enumerate() {
+        match map.cell_vector[idx] {
+            Cell::Room(room) if map.get_room(room).fixed => targets[*component] = true,
+            _ => {}
+        }
+    }
+    if targets.iter().any(|target| *target) {
+        bridge_components(map, &components, &mut joined, &targets, corridor_size);
+    }
+    for (idx, component) in components.iter().enumerate() {
+        match map.cell_vector[idx] {
+            Cell::Room(room) if !joined[*component] && map.get_room(room).fixed => {
+                let room = map.get_room(room);
+                return Err(GenerateError::FixedRoomUnreachable {
+                    room: (room.x, room.y, room.width, room.height),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let mut removed = false;
+    let mut removed_rooms = vec![false; map.iter_rooms().count()];
+    let mut removed_corridors = vec![false; map.iter_corridors().count()];
+    let mut merged_sections = vec![];
+    for (idx, component) in components.iter().enumerate() {
+        if *component == 0 {
+            continue;
+        }
+        if joined[*component] {
+            if let Some(section) = section_index(map, &map.cell_vector[idx]) {
+                merged_sections.push(section);
+            }
+        } else {
+            // Nothing more can be connected, remove the rest
+            match map.cell_vector[idx] {
+                Cell::Room(room) => removed_rooms[room] = true,
+                Cell::Corridor(corridor) => removed_corridors[corridor] = true,
+                _ => {}
+            }
+            map.cell_vector[idx] = Cell::Rock;
+            removed = true;
+        }
+    }
+    for section in merged_sections {
+        map.section_vec[section].set_id(main_id);
+    }
+    if removed {
+        detach_removed_nodes(map);
+        remove_rooms(map, removed_rooms, removed_corridors);
+    }
+    return Ok(());
+}
+
+/**
+ * Takes the rooms and corridors that lost all of their cells out of the
+ * map. Their sections are kept so that section indices stay the same,
+ * but without connections
+ */
+fn remove_rooms(map: &mut Map, mut rooms: Vec<bool>, mut corridors: Vec<bool>) {
+    // Parts of a room or corridor can still be on the map
+    for cell in &map.cell_vector {
+        match cell {
+            Cell::Room(idx) => rooms[*idx] = false,
+            Cell::Corridor(idx) => corridors[*idx] = false,
+            _ => {}
+        }
+    }
+    let mut sections = vec![];
+    for (room, removed) in map.iter_rooms().zip(&rooms) {
+        if *removed {
+            sections.push(room.section_id);
+        }
+    }
+    for (corridor, removed) in map.iter_corridors().zip(&corridors) {
+        if *removed {
+            sections.push(corridor.section_id);
+        }
+    }
+    for section in sections {
+        map.section_vec[section].connections.clear();
+    }
+    map.remove_rooms(&rooms, &corridors);
+}
+
+/**
+ * Labels the groups of walkable cells that are connected to each other,
+ * 0 is used for cells that aren't walkable. Returns the labels and how
+ * many groups there are
+ */
+fn label_components(map: &Map) -> (Vec<usize>, usize) {
+    let width = map.width as i32;
+    let height = map.height as i32;
+    let mut components = vec![0usize; map.cell_vector.len()];
+    let mut count = 0;
+    for start in 0..map.cell_vector.len() {
+        if components[start] != 0 || !map.cell_vector[start].is_walkable() {
+            continue;
+        }
+        count += 1;
+        components[start] = count;
+        let mut stack = vec![start];
+        while let Some(idx) = stack.pop() {
+            let (x, y) = (
+                (idx % map.width as usize) as i32,
+                (idx / map.width as usize) as i32,
+            );
+            for (n_x, n_y) in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)].iter() {
+                if *n_x < 0 || *n_y < 0 || *n_x >= width || *n_y >= height {
+                    continue;
+                }
+                let n_idx = (n_y * width + n_x) as usize;
+                if components[n_idx] == 0 && map.cell_vector[n_idx].is_walkable() {
+                    components[n_idx] = count;
+                    stack.push(n_idx);
+                }
+            }
+        }
+    }
+    return (components, count);
+}
+
+/**
+ * The group with the most cells of the main section,
+ * or the largest group if the main section has no cells
+ */
+fn find_main_component(map: &Map, components: &[usize], main_id: usize) -> usize {
+    let mut sizes: HashMap<usize, (usize, usize)> = HashMap::new();
+    for (idx, component) in components.iter().enumerate() {
+        if *component == 0 {
+            continue;
+        }
+        let size = sizes.entry(*component).or_insert((0, 0));
+        match section_index(map, &map.cell_vector[idx]) {
+            Some(section) if map.section_vec[section].get_id() == main_id => size.0 += 1,
+            _ => {}
+        }
+        size.1 += 1;
+    }
+    return sizes
+        .into_iter()
+        .max_by_key(|(component, size)| (*size, usize::MAX - *component))
+        .map(|(component, _)| component)
+        .unwrap();
+}
+
+/**
+ * Grows outwards from the joined groups through rock, a corridor sized
+ * piece at a time. Every target group that is reached gets a corridor
+ * carved to it and is joined, after which the growth continues from it as well
+ */
+fn bridge_components(
+    map: &mut Map,
+    components: &[usize],
+    joined: &mut [bool],
+    targets: &[bool],
+    size: (u32, u32),
+) {
+    let width = map.width as i32;
+    let height = map.height as i32;
+    let (size_x, size_y) = (size.0 as i32, size.1 as i32);
+    let area = CarveArea::new(map, size);
+    // Rooms with sockets are only bridged to through their sockets
+    let sealed: Vec<bool> = map
+        .iter()
+        .map(|(x, y, _)| is_sealed(map, x as i32, y as i32))
+        .collect();
+    let component = |x: i32, y: i32| -> usize {
+        if x < 0 || y < 0 || x >= width || y >= height || sealed[(y * width + x) as usize] {
+            return 0;
+        }
+        return components[(y * width + x) as usize];
+    };
+    let mut group_cells: Vec<Vec<usize>> = vec![vec![]; joined.len()];
+    for (idx, component) in components.iter().enumerate() {
+        if *component != 0 && !sealed[idx] {
+            group_cells[*component].push(idx);
+        }
+    }
+
+    let mut parents: Vec<Option<usize>> = vec![None; (width * height) as usize];
+    let mut visited = vec![false; (width * height) as usize];
+    let mut queue = VecDeque::new();
+    // Queues every free piece that shares an edge with one of the cells
+    let seed = |cells: &[usize], visited: &mut Vec<bool>, queue: &mut VecDeque<(i32, i32)>| {
+        for idx in cells {
+            let (x, y) = ((*idx as i32) % width, (*idx as i32) / width);
+            for (n_x, n_y) in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)].iter() {
+                for pos_y in (n_y - size_y + 1)..=*n_y {
+                    for pos_x in (n_x - size_x + 1)..=*n_x {
+                        if area.is_free(pos_x, pos_y) && !visited[(pos_y * width + pos_x) as usize]
+                        {
+                            visited[(pos_y * width + pos_x) as usize] = true;
+                            queue.push_back((pos_x, pos_y));
+                        }
+                    }
+                }
+            }
+        }
+    };
+    // The first group that isn't joined yet and shares an edge with the piece
+    let border_group = |x: i32, y: i32, joined: &[bool]| -> Option<usize> {
+        let is_new = |pos_x: i32, pos_y: i32| {
+            let group = component(pos_x, pos_y);
+            return group != 0 && !joined[group] && targets[group];
+        };
+        for pos_x in x..(x + size_x) {
+            for pos_y in [y - 1, y + size_y].iter() {
+                if is_new(pos_x, *pos_y) {
+                    return Some(component(pos_x, *pos_y));
+                }
+            }
+        }
+        for pos_y in y..(y + size_y) {
+            for pos_x in [x - 1, x + size_x].iter() {
+                if is_new(*pos_x, pos_y) {
+                    return Some(component(*pos_x, pos_y));
+                }
+            }
+        }
+        return None;
+    };
+    for group in 0..joined.len() {
+        if joined[group] {
+            seed(&group_cells[group], &mut visited, &mut queue);
+        }
+    }
+    while let Some((x, y)) = queue.pop_front() {
+        while let Some(group) = border_group(x, y, joined) {
+            let mut current = Some((y * width + x) as usize);
+            while let Some(idx) = current {
+                let (piece_x, piece_y) = ((idx as i32 % width) as u32, (idx as i32 / width) as u32);
+                for pos_y in piece_y..(piece_y + size.1) {
+                    for pos_x in piece_x..(piece_x + size.0) {
+                        if map.get(pos_x as i32, pos_y as i32).is_rock() {
+                            map.set(pos_x, pos_y, Cell::Connection);
+                        }
+                    }
+                }
+                current = parents[idx];
+            }
+            joined[group] = true;
+            seed(&group_cells[group], &mut visited, &mut queue);
+        }
+        for (n_x, n_y) in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)].iter() {
+            if !area.is_free(*n_x, *n_y) {
+                continue;
+            }
+            let idx = (n_y * width + n_x) as usize;
+            if !visited[idx] {
+                visited[idx] = true;
+                parents[idx] = Some((y * width + x) as usize);
+                queue.push_back((*n_x, *n_y));
+            }
+        }
+    }
+}
+
+/**
+ * Summed area table of the cells that can't be carved, to quickly check
+ * if a corridor sized piece fits somewhere
+ */
+struct CarveArea {
+    blocked: Vec<u32>,
+    stride: usize,
+    width: i32,
+    height: i32,
+    size: (i32, i32),
+}
+
+impl CarveArea {
+    fn new(map: &Map, size: (u32, u32)) -> CarveArea {
+        let width = map.width as i32;
+        let height = map.height as i32;
+        let stride = (width + 1) as usize;
+        let mut blocked = vec![0u32; stride * (height + 1) as usize];
+        for y in 0..height {
+            for x in 0..width {
+                let is_blocked = (!map.get(x, y).is_rock() || is_guarded(map, x, y, size)) as u32;
+                blocked[(y + 1) as usize * stride + (x + 1) as usize] = is_blocked
+                    + blocked[y as usize * stride + (x + 1) as usize]
+                    + blocked[(y + 1) as usize * stride + x as usize]
+                    - blocked[y as usize * stride + x as usize];
+            }
+        }
+        return CarveArea {
+            blocked,
+            stride,
+            width,
+            height,
+            size: (size.0 as i32, size.1 as i32),
+        };
+    }
+    /**
+     * Returns true if a piece with its top left corner at the position
+     * is inside of the map and only covers rock
+     */
+    fn is_free(&self, x: i32, y: i32) -> bool {
+        if x < 0 || y < 0 || x + self.size.0 > self.width || y + self.size.1 > self.height {
+            return false;
+        }
+        let (x, y) = (x as usize, y as usize);
+        let (x2, y2) = (x + self.size.0 as usize, y + self.size.1 as usize);
+        let stride = self.stride;
+        return self.blocked[y2 * stride + x2] + self.blocked[y * stride + x]
+            - self.blocked[y * stride + x2]
+            - self.blocked[y2 * stride + x]
+            == 0;
+    }
+}
+
+/**
+ * Whether the cell belongs to a room with sockets without being a socket
+ */
+fn is_sealed(map: &Map, x: i32, y: i32) -> bool {
+    match map.get(x, y) {
+        Cell::Room(idx) => {
+            let room = map.get_room(*idx);
+            return room.has_sockets() && !room.is_socket(x, y);
+        }
+        _ => return false,
+    }
+}
+
+/**
+ * Bridges can't run along the walls of rooms with sockets, only a corridor
+ * size away from a socket so they can end in front of it
+ */
+fn is_guarded(map: &Map, x: i32, y: i32, size: (u32, u32)) -> bool {
+    if ![(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)]
+        .iter()
+        .any(|(n_x, n_y)| is_sealed(map, *n_x, *n_y))
+    {
+        return false;
+    }
+    let reach = size.0.max(size.1) as i32;
+    for pos_y in (y - reach)..=(y + reach) {
+        for pos_x in (x - reach)..=(x + reach) {
+            match map.get(pos_x, pos_y) {
+                Cell::Room(idx) if map.get_room(*idx).is_socket(pos_x, pos_y) => return false,
+                _ => {}
+            }
+        }
+    }
+    return true;
+}
+
+pub(crate) fn section_index(map: &Map, cell: &Cell) -> Option<usize> {
+    match cell {
+        Cell::Room(idx) => return Some(map.get_room(*idx).section_id),
+        Cell::Corridor(idx) => return Some(map.get_corridor(*idx).section_id),
+        _ => return None,
+    }
+}
+
+/**
+ * Finds the shortest path of corridor sized pieces through rock from a
+ * cell labeled 1 to a cell labeled 2. Returns the top left position of
+ * every piece and the cells the path starts and ends next to
+ */
+#[allow(clippy::type_complexity)]
+pub(crate) fn find_bridge(
+    map: &Map,
+    labels: &[u8],
+    size: (u32, u32),
+) -> Option<(Vec<(u32, u32)>, (u32, u32), (u32, u32))> {
+    let width = map.width as i32;
+    let height = map.height as i32;
+    let label = |x: i32, y: i32| -> u8 {
+        if x < 0 || y < 0 || x >= width || y >= height {
+            return 0;
+        }
+        return labels[(y * width + x) as usize];
+    };
+    // The cells are already next to each other
+    for y in 0..height {
+        for x in 0..width {
+            if label(x, y) != 1 {
+                continue;
+            }
+            for (n_x, n_y) in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)].iter() {
+                if label(*n_x, *n_y) == 2 {
+                    return Some((vec![], (x as u32, y as u32), (*n_x as u32, *n_y as u32)));
+                }
+            }
+        }
+    }
+
+    let (size_x, size_y) = (size.0 as i32, size.1 as i32);
+    let area = CarveArea::new(map, size);
+    let is_free = |x: i32, y: i32| area.is_free(x, y);
+    // Returns the first cell with the label that shares an edge with the piece
+    let touches = |x: i32, y: i32, wanted: u8| -> Option<(u32, u32)> {
+        for pos_x in x..(x + size_x) {
+            for pos_y in [y - 1, y + size_y].iter() {
+                if label(pos_x, *pos_y) == wanted {
+                    return Some((pos_x as u32, *pos_y as u32));
+                }
+            }
+        }
+        for pos_y in y..(y + size_y) {
+            for pos_x in [x - 1, x + size_x].iter() {
+                if label(*pos_x, pos_y) == wanted {
+                    return Some((*pos_x as u32, pos_y as u32));
+                }
+            }
+        }
+        return None;
+    };
+
+    let mut parents: Vec<Option<usize>> = vec![None; (width * height) as usize];
+    let mut visited = vec![false; (width * height) as usize];
+    let mut queue = VecDeque::new();
+    for y in 0..height {
+        for x in 0..width {
+            if is_free(x, y) && touches(x, y, 1).is_some() {
+                visited[(y * width + x) as usize] = true;
+                queue.push_back((x, y));
+            }
+        }
+    }
+    while let Some((x, y)) = queue.pop_front() {
+        if let Some(end) = touches(x, y, 2) {
+            let mut path = vec![(x as u32, y as u32)];
+            let mut current = (y * width + x) as usize;
+            while let Some(parent) = parents[current] {
+                path.push((
+                    (parent as i32 % width) as u32,
+                    (parent as i32 / width) as u32,
+                ));
+                current = parent;
+            }
+            path.reverse();
+            let first = path[0];
+            let start = touches(first.0 as i32, first.1 as i32, 1).unwrap();
+            return Some((path, start, end));
+        }
+        for (n_x, n_y) in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)].iter() {
+            if !is_free(*n_x, *n_y) {
+                continue;
+            }
+            let idx = (n_y * width + n_x) as usize;
+            if !visited[idx] {
+                visited[idx] = true;
+                parents[idx] = Some((y * width + x) as usize);
+                queue.push_back((*n_x, *n_y));
+            }
+        }
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cave::regions;
+    use crate::map_generator::Generator;
+    use crate::room::{Room, RoomShape};
+    use crate::room_generator::add_room;
+
+    /**
+     * A map with two rooms that are apart from each other, the second one
+     * is walled in by solid rock when `walled_in` is set
+     */
+    fn two_rooms(walled_in: bool) -> Map {
+        let options = Generator::new().options;
+        let mut map = Map::new(24, 10, Cell::Rock);
+        if walled_in {
+            map.set_rect(Cell::SolidRock, 12, 0, 1, 10);
+        }
+        for x in [2, 16].iter() {
+            let room = Room::from_footprint(*x, 3, 4, 4, RoomShape::Rectangle, vec![true; 16], 0);
+            add_room(&mut map, room, &options);
+        }
+        return map;
+    }
+
+    /**
+     * How many groups of walkable cells the map has
+     */
+    fn region_count(map: &Map) -> usize {
+        let rock: Vec<bool> = map.cell_vector.iter().map(|c| !c.is_walkable()).collect();
+        return regions(&rock, (map.width as i32, map.height as i32)).len();
+    }
+
+    #[test]
+    fn reconnect_joins_every_room() {
+        let mut map = two_rooms(false);
+        let main_id = map.get_room(0).section_id;
+        ensure_connected(&mut map, main_id, (1, 1), DisconnectedPolicy::Reconnect).unwrap();
+        assert_eq!(region_count(&map), 1);
+        assert_eq!(map.iter_rooms().len(), 2);
+    }
+
+    #[test]
+    fn remove_drops_the_unreachable_rooms() {
+        let mut map = two_rooms(false);
+        let main_id = map.get_room(0).section_id;
+        ensure_connected(&mut map, main_id, (1, 1), DisconnectedPolicy::Remove).unwrap();
+        assert_eq!(region_count(&map), 1);
+        assert_eq!(map.iter_rooms().len(), 1);
+    }
+
+    #[test]
+    fn reconnect_drops_the_rooms_behind_solid_rock() {
+        let mut map = two_rooms(true);
+        let main_id = map.get_room(0).section_id;
+        ensure_connected(&mut map, main_id, (1, 1), DisconnectedPolicy::Reconnect).unwrap();
+        assert_eq!(region_count(&map), 1);
+        assert_eq!(map.iter_rooms().len(), 1);
+    }
+
+    #[test]
+    fn generated_maps_are_one_region() {
+        for policy in [DisconnectedPolicy::Reconnect, DisconnectedPolicy::Remove].iter() {
+            for seed in 0..10 {
+                let map = Generator::new()
+                    .seed(seed)
+                    .disconnected_policy(*policy)
+                    .generate();
+                assert_eq!(region_count(&map), 1, "{:?} seed {}", policy, seed);
+            }
+        }
+    }
+}
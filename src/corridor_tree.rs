@@ -7,8 +7,8 @@ type WeakWrappedCorridorNode = Weak<RefCell<CorridorNode>>;
 pub struct CorridorNode {
     pub parent: Option<WeakWrappedCorridorNode>,
     pub children: Vec<WrappedCorridorNode>,
-    pub x: u16,
-    pub y: u16,
+    pub x: u32,
+    pub y: u32,
 }
 
 impl PartialEq for CorridorNode {
@@ -17,6 +17,22 @@ impl PartialEq for CorridorNode {
     }
 }
 
+impl Drop for CorridorNode {
+    /**
+     * Drops the children with a stack, dropping them recursively overflows
+     * the stack on very long corridors
+     */
+    fn drop(&mut self) {
+        let mut stack = std::mem::take(&mut self.children);
+        while let Some(child) = stack.pop() {
+            // Only the last reference owns the children
+            if let Ok(child) = Rc::try_unwrap(child) {
+                stack.append(&mut child.borrow_mut().children);
+            }
+        }
+    }
+}
+
 pub fn set_parent(parent: &WrappedCorridorNode, child: &WrappedCorridorNode) {
     child.borrow_mut().parent = Some(Rc::downgrade(parent));
 }
@@ -44,7 +60,7 @@ pub fn remove_node(node: &WrappedCorridorNode) {
 }
 
 impl CorridorNode {
-    pub fn new(parent: Option<&WrappedCorridorNode>, x: u16, y: u16) -> WrappedCorridorNode {
+    pub fn new(parent: Option<&WrappedCorridorNode>, x: u32, y: u32) -> WrappedCorridorNode {
         let node = Rc::new(RefCell::new(CorridorNode {
             parent: None,
             children: vec![],
@@ -52,8 +68,29 @@ impl CorridorNode {
             y,
         }));
         if let Some(p) = parent {
-            add_child(&p, &node)
+            add_child(p, &node)
         }
         return node;
     }
 }
+
+/**
+ * Copies the tree with every node moved by the transform
+ */
+pub fn transform_tree<F>(root: &WrappedCorridorNode, transform: F) -> WrappedCorridorNode
+where
+    F: Fn(u32, u32) -> (u32, u32),
+{
+    let (x, y) = transform(root.borrow().x, root.borrow().y);
+    let new_root = CorridorNode::new(None, x, y);
+    // Walk the tree with a stack as corridors can be very deep
+    let mut stack = vec![(Rc::clone(root), Rc::clone(&new_root))];
+    while let Some((node, new_node)) = stack.pop() {
+        for child in &node.borrow().children {
+            let (x, y) = transform(child.borrow().x, child.borrow().y);
+            let new_child = CorridorNode::new(Some(&new_node), x, y);
+            stack.push((Rc::clone(child), new_child));
+        }
+    }
+    return new_root;
+}
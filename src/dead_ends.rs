@@ -0,0 +1,382 @@
+use crate::cell_matrix::{Cell, Map};
+use crate::corridor_tree::{CorridorNode, WrappedCorridorNode};
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeadEndRemoval {
+    // Trims the last corridor piece of dead ends shorter than the prune length
+    Short,
+    // Removes dead ends until every corridor leads somewhere
+    All,
+    // Removes a share (0-1) of the corridor cells that `All` would remove,
+    // every dead end is shortened a bit at a time
+    Percentage(f32),
+}
+
+#[derive(Clone)]
+struct Node {
+    node: WrappedCorridorNode,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    removed: bool,
+    // How many sides of the corridor piece are next to a connection or a
+    // room, each counts as a neighbour that's never removed
+    anchors: usize,
+}
+
+/**
+ * Removes dead end corridor pieces, turning their cells back into rock
+ * and removing them from the corridor tree
+ */
+pub fn remove_dead_ends(map: &mut Map, corridor_size: (u8, u8), removal: DeadEndRemoval) {
+    let size = (corridor_size.0 as u32, corridor_size.1 as u32);
+    let mut nodes = collect_nodes(map, size);
+
+    // How many corridor pieces cover each cell, as pieces overlap a cell
+    // is only removed when every piece covering it is
+    let mut coverage = vec![0u32; map.cell_vector.len()];
+    for node in &nodes {
+        for idx in piece_cells(map, &node.node, size) {
+            coverage[idx] += 1;
+        }
+    }
+
+    let limit = match removal {
+        DeadEndRemoval::Percentage(share) => {
+            let removable = count_removable_cells(map, &nodes, &coverage, size);
+            // Shares outside of 0-1 remove nothing or everything
+            let limit = (removable as f32 * share).max(0f32).ceil() as usize;
+            limit.min(removable)
+        }
+        _ => usize::MAX,
+    };
+    peel_dead_ends(map, &mut nodes, &mut coverage, size, limit);
+    rebuild_tree(map, &nodes);
+}
+
+/**
+ * Removes dead ends until there are none left or the limit of removed
+ * cells is reached, returns how many cells were removed
+ */
+fn peel_dead_ends(
+    map: &mut Map,
+    nodes: &mut [Node],
+    coverage: &mut [u32],
+    size: (u32, u32),
+    limit: usize,
+) -> usize {
+    let mut removed_cells = 0;
+    'rounds: loop {
+        // Peel every current dead end at once so that all dead ends get
+        // shorter at the same pace
+        let dead_ends: Vec<usize> = (0..nodes.len())
+            .filter(|idx| is_dead_end(map, nodes, *idx, size))
+            .collect();
+        if dead_ends.is_empty() {
+            break;
+        }
+        for idx in dead_ends {
+            if removed_cells >= limit {
+                break 'rounds;
+            }
+            nodes[idx].removed = true;
+            let cells = piece_cells(map, &nodes[idx].node, size);
+            for cell_idx in cells.iter().cloned() {
+                coverage[cell_idx] -= 1;
+                if coverage[cell_idx] == 0 && map.cell_vector[cell_idx].is_corridor() {
+                    map.cell_vector[cell_idx] = Cell::Rock;
+                    removed_cells += 1;
+                }
+            }
+            remove_loose_connections(map, &cells);
+        }
+    }
+    return removed_cells;
+}
+
+/**
+ * Turns the connections next to the removed cells into rock when they no
+ * longer lead to a corridor or another room, the last piece of a corridor
+ * can be the only thing a connection leads to
+ */
+fn remove_loose_connections(map: &mut Map, cells: &[usize]) {
+    let width = map.width as usize;
+    for cell_idx in cells {
+        let (x, y) = ((cell_idx % width) as u32, (cell_idx / width) as u32);
+        if !map.get(x as i32, y as i32).is_rock() {
+            continue;
+        }
+        let starts: Vec<(u32, u32)> = map
+            .neighbors4(x, y)
+            .filter(|(_, _, cell)| cell.is_connection())
+            .map(|(n_x, n_y, _)| (n_x, n_y))
+            .collect();
+        for start in starts {
+            let mut connection = vec![start];
+            let mut stack = vec![start];
+            let mut leads_somewhere = false;
+            let mut rooms = vec![];
+            while let Some((pos_x, pos_y)) = stack.pop() {
+                for (n_x, n_y, cell) in map.neighbors4(pos_x, pos_y) {
+                    match cell {
+                        Cell::Corridor(_) => leads_somewhere = true,
+                        Cell::Room(room) if !rooms.contains(room) => rooms.push(*room),
+                        _ => {}
+                    }
+                    if cell.is_connection() && !connection.contains(&(n_x, n_y)) {
+                        connection.push((n_x, n_y));
+                        stack.push((n_x, n_y));
+                    }
+                }
+            }
+            // A connection between two rooms still joins them
+            if !leads_somewhere && rooms.len() < 2 {
+                for (pos_x, pos_y) in connection {
+                    map.set(pos_x, pos_y, Cell::Rock);
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Detaches the corridor pieces that are no longer corridors on the map
+ */
+pub(crate) fn detach_removed_nodes(map: &mut Map) {
+    let mut nodes = collect_nodes(map, (1, 1));
+    for node in nodes.iter_mut() {
+        let (x, y) = (node.node.borrow().x, node.node.borrow().y);
+        node.removed = !map.get(x as i32, y as i32).is_corridor();
+    }
+    rebuild_tree(map, &nodes);
+}
+
+fn collect_nodes(map: &Map, size: (u32, u32)) -> Vec<Node> {
+    let mut nodes: Vec<Node> = vec![];
+    for root_node in &map.corridor_tree {
+        let mut stack = vec![(Rc::clone(root_node), None)];
+        while let Some((node, parent)) = stack.pop() {
+            let idx = nodes.len();
+            if let Some(parent) = parent {
+                let parent_node: &mut Node = &mut nodes[parent];
+                parent_node.children.push(idx);
+            }
+            for child in &node.borrow().children {
+                stack.push((Rc::clone(child), Some(idx)));
+            }
+            let anchors = count_anchors(map, &node, size);
+            nodes.push(Node {
+                node,
+                parent,
+                children: vec![],
+                removed: false,
+                anchors,
+            });
+        }
+    }
+    return nodes;
+}
+
+fn piece_cells(map: &Map, node: &WrappedCorridorNode, size: (u32, u32)) -> Vec<usize> {
+    let node = node.borrow();
+    let mut cells = vec![];
+    for y in node.y..(node.y + size.1).min(map.height) {
+        for x in node.x..(node.x + size.0).min(map.width) {
+            cells.push(y as usize * map.width as usize + x as usize);
+        }
+    }
+    return cells;
+}
+
+/**
+ * The sides of the piece that a room or connection is right next to,
+ * corners don't count as the piece doesn't lead there
+ */
+fn count_anchors(map: &Map, node: &WrappedCorridorNode, size: (u32, u32)) -> usize {
+    let node = node.borrow();
+    let (x, y) = (node.x as i32, node.y as i32);
+    let (width, height) = (size.0 as i32, size.1 as i32);
+    let is_anchor = |x: i32, y: i32| {
+        let cell = map.get(x, y);
+        return cell.is_walkable() && !cell.is_corridor();
+    };
+    let sides = [
+        (x..x + width).any(|pos_x| is_anchor(pos_x, y - 1)),
+        (y..y + height).any(|pos_y| is_anchor(x + width, pos_y)),
+        (x..x + width).any(|pos_x| is_anchor(pos_x, y + height)),
+        (y..y + height).any(|pos_y| is_anchor(x - 1, pos_y)),
+    ];
+    return sides.iter().filter(|side| **side).count();
+}
+
+fn is_dead_end(map: &Map, nodes: &[Node], idx: usize, size: (u32, u32)) -> bool {
+    let node = &nodes[idx];
+    if node.removed {
+        return false;
+    }
+    let mut neighbours = node.anchors;
+    neighbours += node.children.iter().filter(|c| !nodes[**c].removed).count();
+    if let Some(parent) = node.parent {
+        if !nodes[parent].removed {
+            neighbours += 1;
+        }
+    }
+    return neighbours <= 1 && !touches_other_piece(map, nodes, idx, size);
+}
+
+/**
+ * Whether the cells that removing the piece would turn into rock are next
+ * to the corridor of a piece it isn't joined to in the tree. Tunnels that
+ * cross each other are only joined on the map
+ */
+fn touches_other_piece(map: &Map, nodes: &[Node], idx: usize, size: (u32, u32)) -> bool {
+    let node = &nodes[idx];
+    // The piece it's still joined to in the tree, its cells stay
+    let joined = match node.parent {
+        Some(parent) if !nodes[parent].removed => Some(parent),
+        _ => node.children.iter().cloned().find(|c| !nodes[*c].removed),
+    };
+    let covers = |idx: usize, x: u32, y: u32| {
+        let node = nodes[idx].node.borrow();
+        return (node.x..node.x + size.0).contains(&x) && (node.y..node.y + size.1).contains(&y);
+    };
+    let is_joined = |x: u32, y: u32| matches!(joined, Some(j) if covers(j, x, y));
+    let is_kept = |x: u32, y: u32| covers(idx, x, y) || is_joined(x, y);
+    let width = map.width as usize;
+    for cell_idx in piece_cells(map, &node.node, size) {
+        let (x, y) = ((cell_idx % width) as u32, (cell_idx / width) as u32);
+        if is_joined(x, y) {
+            continue;
+        }
+        if map
+            .neighbors4(x, y)
+            .any(|(n_x, n_y, cell)| cell.is_corridor() && !is_kept(n_x, n_y))
+        {
+            return true;
+        }
+    }
+    return false;
+}
+
+/**
+ * Counts the cells that removing every dead end would remove, by removing
+ * them and putting the cells back afterwards
+ */
+fn count_removable_cells(
+    map: &mut Map,
+    nodes: &[Node],
+    coverage: &[u32],
+    size: (u32, u32),
+) -> usize {
+    let cells = map.cell_vector.clone();
+    let mut nodes = nodes.to_vec();
+    let mut coverage = coverage.to_vec();
+    let removable = peel_dead_ends(map, &mut nodes, &mut coverage, size, usize::MAX);
+    map.cell_vector = cells;
+    return removable;
+}
+
+/**
+ * Detaches the removed nodes, nodes that lost their parent become new roots
+ */
+fn rebuild_tree(map: &mut Map, nodes: &[Node]) {
+    let removed: HashSet<*const RefCell<CorridorNode>> = nodes
+        .iter()
+        .filter(|node| node.removed)
+        .map(|node| Rc::as_ptr(&node.node))
+        .collect();
+    let mut roots = vec![];
+    for node in nodes.iter().filter(|node| !node.removed) {
+        node.node
+            .borrow_mut()
+            .children
+            .retain(|child| !removed.contains(&Rc::as_ptr(child)));
+        let has_parent = match node.parent {
+            Some(parent) => !nodes[parent].removed,
+            None => false,
+        };
+        if !has_parent {
+            node.node.borrow_mut().parent = None;
+            roots.push(Rc::clone(&node.node));
+        }
+    }
+    map.corridor_tree = roots;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::GenerateError;
+    use crate::map_generator::Generator;
+
+    #[test]
+    fn removing_every_dead_end_without_rooms() {
+        for seed in 0..20 {
+            let map = Generator::new()
+                .seed(seed)
+                .size(30, 14)
+                .room_size((9, 9), (10, 10))
+                .dead_end_removal(DeadEndRemoval::All)
+                .try_generate();
+            match map {
+                Ok(map) => assert!(map.cell_vector.iter().any(|cell| cell.is_walkable())),
+                Err(err) => assert_eq!(err, GenerateError::NoSections),
+            }
+        }
+    }
+
+    fn corridor_map(seed: u64, removal: DeadEndRemoval) -> Map {
+        return Generator::new()
+            .seed(seed)
+            .corridor_size(1, 1)
+            .dead_end_removal(removal)
+            .generate();
+    }
+
+    /**
+     * Corridor cells with at most one walkable neighbour
+     */
+    fn dead_end_cells(map: &Map) -> usize {
+        return map
+            .iter()
+            .filter(|(x, y, cell)| {
+                cell.is_corridor()
+                    && map
+                        .neighbors4(*x, *y)
+                        .filter(|(_, _, n)| n.is_walkable())
+                        .count()
+                        <= 1
+            })
+            .count();
+    }
+
+    fn corridor_cells(map: &Map) -> usize {
+        return map.iter().filter(|(_, _, cell)| cell.is_corridor()).count();
+    }
+
+    #[test]
+    fn removing_all_leaves_no_dead_ends() {
+        for seed in 0..10 {
+            let short = corridor_map(seed, DeadEndRemoval::Short);
+            let all = corridor_map(seed, DeadEndRemoval::All);
+            assert!(dead_end_cells(&short) > 0);
+            assert_eq!(dead_end_cells(&all), 0);
+            assert!(corridor_cells(&all) < corridor_cells(&short));
+        }
+    }
+
+    #[test]
+    fn removing_a_share_shortens_the_dead_ends() {
+        for seed in 0..10 {
+            let none = corridor_map(seed, DeadEndRemoval::Percentage(0f32));
+            let half = corridor_map(seed, DeadEndRemoval::Percentage(0.5));
+            let all = corridor_map(seed, DeadEndRemoval::Percentage(1f32));
+            assert!(corridor_cells(&half) < corridor_cells(&none));
+            assert!(corridor_cells(&all) < corridor_cells(&half));
+            assert_eq!(dead_end_cells(&all), 0);
+        }
+    }
+}
@@ -1,5 +1,3 @@
-extern crate rand;
-use rand::thread_rng;
 use rand::Rng;
 
 #[derive(Clone, Copy, PartialEq)]
@@ -10,6 +8,14 @@ pub enum Direction {
     W,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Orientation {
+    // Runs from west to east
+    Horizontal,
+    // Runs from north to south
+    Vertical,
+}
+
 impl std::fmt::Display for Direction {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let formatted_string = match self {
@@ -31,8 +37,8 @@ impl Direction {
             Direction::W => Direction::N,
         }
     }
-    pub fn rand() -> Direction {
-        match thread_rng().gen_range(0, 4) {
+    pub fn rand<R: Rng + ?Sized>(rng: &mut R) -> Direction {
+        match rng.gen_range(0, 4) {
             0 => Direction::N,
             1 => Direction::E,
             2 => Direction::S,
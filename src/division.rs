@@ -0,0 +1,209 @@
+use crate::cell_matrix::{Cell, Map};
+use crate::connectivity::ensure_connected;
+use crate::error::GenerateError;
+use crate::map_generator::GeneratorOptions;
+use crate::room::{Room, RoomShape};
+
+use rand::Rng;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecursiveDivision {
+    // 0-1, how much horizontal walls are preferred over vertical ones,
+    // at 0.5 tall areas are usually split horizontally and wide areas vertically
+    pub horizontal_bias: f32,
+    // (width, height), areas that fit are sometimes left unsplit as a room
+    pub chamber_size: (u32, u32),
+    // 0-1, chance that an area that fits the chamber size becomes a room
+    pub chamber_chance: f32,
+}
+
+impl Default for RecursiveDivision {
+    fn default() -> Self {
+        return RecursiveDivision {
+            horizontal_bias: 0.5,
+            chamber_size: (0, 0),
+            chamber_chance: 0f32,
+        };
+    }
+}
+
+/**
+ * Fills the rock of the map with an open area and splits it with walls
+ * until the areas are a corridor wide. The walls are left as rock for
+ * the wall pass and every open cell is part of one corridor
+ */
+pub fn divide<R: Rng>(
+    rng: &mut R,
+    map: &mut Map,
+    division: &RecursiveDivision,
+    options: &GeneratorOptions,
+) -> Result<(), GenerateError> {
+    let corridor_size = (
+        options.corridor_width as u32,
+        options.corridor_height as u32,
+    );
+    // Every cell of the grid is a corridor wide with a wall on each side
+    let step = (corridor_size.0 + 1, corridor_size.1 + 1);
+    let columns = map.width.saturating_sub(1) / step.0;
+    let rows = map.height.saturating_sub(1) / step.1;
+    if columns == 0 || rows == 0 {
+        return Ok(());
+    }
+    let corridor_index = map.add_corridor();
+    let section_id = map.get_corridor(corridor_index).section_id;
+    // Rock next to the outside of the map shape is kept as the outer wall
+    for y in 1..rows * step.1 {
+        for x in 1..columns * step.0 {
+            if map.get(x as i32, y as i32).is_rock()
+                && !map
+                    .neighbors8(x, y)
+                    .any(|(_, _, cell)| *cell == Cell::SolidRock)
+            {
+                map.set(x, y, Cell::Corridor(corridor_index));
+            }
+        }
+    }
+
+    // (x, y, width, height) in grid cells, a stack as maps can be very big
+    let mut areas = vec![(0, 0, columns, rows)];
+    while let Some((x, y, width, height)) = areas.pop() {
+        if width < 2 && height < 2 {
+            continue;
+        }
+        // Rooms are the open area between the walls around it
+        let (room_x, room_y) = (x * step.0 + 1, y * step.1 + 1);
+        let (room_width, room_height) = (width * step.0 - 1, height * step.1 - 1);
+        if width >= 2
+            && height >= 2
+            && room_width <= division.chamber_size.0
+            && room_height <= division.chamber_size.1
+            && rng.gen::<f32>() < division.chamber_chance
+            && map
+                .rect_is(room_x as i32, room_y as i32, room_width, room_height, |c| {
+                    !c.is_corridor()
+                })
+                .is_none()
+        {
+            let room = Room::new(
+                rng,
+                room_x,
+                room_y,
+                room_width,
+                room_height,
+                RoomShape::Rectangle,
+                section_id,
+            );
+            let idx = map.push_room(room);
+            map.set_rect(Cell::Room(idx), room_x, room_y, room_width, room_height);
+            continue;
+        }
+        let horizontal = if width < 2 {
+            true
+        } else if height < 2 {
+            false
+        } else {
+            let horizontal_weight = division.horizontal_bias * height as f32;
+            let vertical_weight = (1f32 - division.horizontal_bias) * width as f32;
+            rng.gen::<f32>() * (horizontal_weight + vertical_weight) < horizontal_weight
+        };
+        if horizontal {
+            let split = rng.gen_range(1, height);
+            let passage = rng.gen_range(x, x + width);
+            let wall_y = (y + split) * step.1;
+            for wall_x in x * step.0..=(x + width) * step.0 {
+                if wall_x % step.0 != 0 && (wall_x - 1) / step.0 == passage {
+                    continue;
+                }
+                add_wall(map, wall_x, wall_y);
+            }
+            areas.push((x, y, width, split));
+            areas.push((x, y + split, width, height - split));
+        } else {
+            let split = rng.gen_range(1, width);
+            let passage = rng.gen_range(y, y + height);
+            let wall_x = (x + split) * step.0;
+            for wall_y in y * step.1..=(y + height) * step.1 {
+                if wall_y % step.1 != 0 && (wall_y - 1) / step.1 == passage {
+                    continue;
+                }
+                add_wall(map, wall_x, wall_y);
+            }
+            areas.push((x, y, split, height));
+            areas.push((x + split, y, width - split, height));
+        }
+    }
+    // Parts of the maze can be cut off by the map shape
+    return ensure_connected(map, section_id, corridor_size, options.disconnected_policy);
+}
+
+fn add_wall(map: &mut Map, x: u32, y: u32) {
+    if map.get(x as i32, y as i32).is_corridor() {
+        map.set(x, y, Cell::Rock);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cave::regions;
+    use crate::map_generator::{Generator, Layout};
+    use crate::map_shape::MapShape;
+
+    /**
+     * A divided 41 by 31 map with 1 cell wide corridors
+     */
+    fn divided(seed: u64, division: RecursiveDivision, shape: MapShape) -> Map {
+        return Generator::new()
+            .seed(seed)
+            .size(41, 31)
+            .shape(shape)
+            .corridor_size(1, 1)
+            .layout(Layout::RecursiveDivision(division))
+            .generate();
+    }
+
+    #[test]
+    fn divided_maps_are_one_region() {
+        for circle in [false, true].iter() {
+            for seed in 0..10 {
+                let shape = if *circle {
+                    MapShape::Circle
+                } else {
+                    MapShape::Square
+                };
+                let map = divided(seed, RecursiveDivision::default(), shape);
+                let rock: Vec<bool> = map.cell_vector.iter().map(|c| !c.is_walkable()).collect();
+                assert_eq!(regions(&rock, (41, 31)).len(), 1, "seed {}", seed);
+                assert!(map.iter().any(|(_, _, cell)| cell.is_corridor()));
+            }
+        }
+    }
+
+    #[test]
+    fn wall_corners_stay_rock() {
+        for seed in 0..10 {
+            let map = divided(seed, RecursiveDivision::default(), MapShape::Square);
+            for (x, y, cell) in map.iter() {
+                if x % 2 == 0 && y % 2 == 0 {
+                    assert!(!cell.is_walkable(), "({}, {}) seed {}", x, y, seed);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn chambers_become_rooms() {
+        let division = RecursiveDivision {
+            chamber_size: (7, 7),
+            chamber_chance: 1f32,
+            ..RecursiveDivision::default()
+        };
+        for seed in 0..10 {
+            let map = divided(seed, division, MapShape::Square);
+            assert!(map.iter_rooms().len() > 0, "seed {}", seed);
+            for room in map.iter_rooms() {
+                assert!(room.width <= 7 && room.height <= 7);
+            }
+        }
+    }
+}
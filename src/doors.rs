@@ -0,0 +1,199 @@
+use crate::cell_matrix::{Cell, Map};
+use crate::direction::{Direction, Orientation};
+use crate::sections::Connection;
+
+use rand::Rng;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DoorKind {
+    // An open doorway without a door
+    Archway,
+    Door,
+    // A door that needs a key
+    Locked,
+    Portcullis,
+    // Looks like the wall around it until it's found
+    Secret,
+}
+
+/**
+ * Places a door where an opened connection meets a room, the door is one
+ * cell deep and `door_width` cells wide. The rest of the connection stays
+ * a plain connection. Connections between two corridors don't get doors
+ */
+pub fn place_doors<R: Rng>(
+    rng: &mut R,
+    map: &mut Map,
+    connections: &[(Connection, (u32, u32))],
+    door_width: u8,
+    kinds: &[(DoorKind, u32)],
+) {
+    let total_weight: u32 = kinds.iter().map(|(_, weight)| weight).sum();
+    if total_weight == 0 {
+        return;
+    }
+    for (connection, (width, height)) in connections {
+        let (x, y) = (connection.x as i32, connection.y as i32);
+        let (width, height) = (*width as i32, *height as i32);
+        // The line of cells along the room boundary, which way the line goes,
+        // and which side of the line the room is on
+        let (line_x, line_y, step, length, room_offset, orientation) = match connection.direction {
+            Direction::N | Direction::S => {
+                if is_room_side(map, x, y - 1, (1, 0), width) {
+                    (x, y, (1, 0), width, (0, -1), Orientation::Horizontal)
+                } else if is_room_side(map, x, y + height, (1, 0), width) {
+                    (
+                        x,
+                        y + height - 1,
+                        (1, 0),
+                        width,
+                        (0, 1),
+                        Orientation::Horizontal,
+                    )
+                } else {
+                    continue;
+                }
+            }
+            Direction::E | Direction::W => {
+                if is_room_side(map, x - 1, y, (0, 1), height) {
+                    (x, y, (0, 1), height, (-1, 0), Orientation::Vertical)
+                } else if is_room_side(map, x + width, y, (0, 1), height) {
+                    (
+                        x + width - 1,
+                        y,
+                        (0, 1),
+                        height,
+                        (1, 0),
+                        Orientation::Vertical,
+                    )
+                } else {
+                    continue;
+                }
+            }
+        };
+        let door_length = (door_width as i32).min(length);
+        let door_start = (length - door_length) / 2;
+        let line: Vec<(i32, i32)> = (0..length)
+            .map(|i| (line_x + step.0 * i, line_y + step.1 * i))
+            .collect();
+        let door = &line[door_start as usize..(door_start + door_length) as usize];
+        // Another connection might already have been turned into a door, and
+        // the door has to open into the room
+        if line
+            .iter()
+            .any(|(pos_x, pos_y)| !map.get(*pos_x, *pos_y).is_connection())
+            || door.iter().any(|(pos_x, pos_y)| {
+                !map.get(pos_x + room_offset.0, pos_y + room_offset.1)
+                    .is_room()
+            })
+        {
+            continue;
+        }
+        let kind = pick_kind(rng, kinds, total_weight);
+        for (idx, (pos_x, pos_y)) in line.iter().enumerate() {
+            let cell = if idx as i32 >= door_start && (idx as i32) < door_start + door_length {
+                Cell::Door(orientation, kind)
+            } else {
+                Cell::Rock
+            };
+            map.set(*pos_x as u32, *pos_y as u32, cell);
+        }
+    }
+}
+
+fn is_room_side(map: &Map, x: i32, y: i32, step: (i32, i32), length: i32) -> bool {
+    return (0..length).any(|i| map.get(x + step.0 * i, y + step.1 * i).is_room());
+}
+
+fn pick_kind<R: Rng>(rng: &mut R, kinds: &[(DoorKind, u32)], total_weight: u32) -> DoorKind {
+    let mut roll = rng.gen_range(0, total_weight);
+    for (kind, weight) in kinds {
+        if roll < *weight {
+            return *kind;
+        }
+        roll -= weight;
+    }
+    return kinds[kinds.len() - 1].0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_generator::Generator;
+
+    /**
+     * The door cells of a map with 3 cells wide corridors
+     */
+    fn doors(seed: u64, door_width: u8, kinds: Vec<(DoorKind, u32)>) -> (Map, Vec<(u32, u32)>) {
+        let map = Generator::new()
+            .seed(seed)
+            .corridor_size(3, 3)
+            .door_width(door_width)
+            .door_kinds(kinds)
+            .generate();
+        let doors = map
+            .iter()
+            .filter(|(_, _, cell)| matches!(cell, Cell::Door(_, _)))
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        return (map, doors);
+    }
+
+    #[test]
+    fn no_door_kinds_no_doors() {
+        for seed in 0..5 {
+            assert!(doors(seed, 1, vec![]).1.is_empty());
+        }
+    }
+
+    #[test]
+    fn doors_open_into_rooms() {
+        let kinds = vec![(DoorKind::Door, 1), (DoorKind::Locked, 1)];
+        let mut placed = 0;
+        for seed in 0..10 {
+            let (map, doors) = doors(seed, 1, kinds.clone());
+            placed += doors.len();
+            for (x, y) in doors {
+                let (x, y) = (x as i32, y as i32);
+                match map.get(x, y) {
+                    Cell::Door(Orientation::Horizontal, kind) => {
+                        assert!(map.get(x, y - 1).is_room() || map.get(x, y + 1).is_room());
+                        assert!(*kind == DoorKind::Door || *kind == DoorKind::Locked);
+                    }
+                    Cell::Door(Orientation::Vertical, kind) => {
+                        assert!(map.get(x - 1, y).is_room() || map.get(x + 1, y).is_room());
+                        assert!(*kind == DoorKind::Door || *kind == DoorKind::Locked);
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+        assert!(placed > 0);
+    }
+
+    #[test]
+    fn doors_keep_their_width() {
+        for door_width in 1..=3 {
+            for seed in 0..5 {
+                let (map, doors) = doors(seed, door_width, vec![(DoorKind::Archway, 1)]);
+                for (x, y) in doors {
+                    let (x, y) = (x as i32, y as i32);
+                    let step = match map.get(x, y) {
+                        Cell::Door(Orientation::Horizontal, _) => (1, 0),
+                        _ => (0, 1),
+                    };
+                    // Only count from the first cell of each door
+                    if matches!(map.get(x - step.0, y - step.1), Cell::Door(_, _)) {
+                        continue;
+                    }
+                    let width = (0..)
+                        .take_while(|i| {
+                            matches!(map.get(x + step.0 * i, y + step.1 * i), Cell::Door(_, _))
+                        })
+                        .count();
+                    assert_eq!(width, door_width as usize, "({}, {}) seed {}", x, y, seed);
+                }
+            }
+        }
+    }
+}
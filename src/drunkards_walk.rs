@@ -0,0 +1,267 @@
+use crate::cell_matrix::{Cell, Map};
+use crate::corridor_tree::{CorridorNode, WrappedCorridorNode};
+use crate::direction::Direction;
+use crate::map_generator::GeneratorOptions;
+use crate::maze::MazeAlgorithm;
+
+use rand::{Rng, RngCore};
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/**
+ * Random walkers that dig organic tunnels through the rock, either between
+ * the rooms as a `MazeAlgorithm` or as the whole map with `Layout::DrunkardsWalk`
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DrunkardsWalk {
+    // How many walkers dig at the same time
+    pub walkers: u32,
+    // How many steps every walker takes at most
+    pub steps: u32,
+    // 0-1, chance that a walker picks a new direction every step
+    pub turn_chance: f32,
+    // 0-1, the walkers stop once the tunnels cover this share of the map
+    pub floor_share: f32,
+}
+
+impl Default for DrunkardsWalk {
+    fn default() -> Self {
+        return DrunkardsWalk {
+            walkers: 4,
+            steps: 2000,
+            turn_chance: 0.2,
+            floor_share: 0.3,
+        };
+    }
+}
+
+// A walker digging a tunnel, (x, y) is the top left of the corridor piece
+struct Walker {
+    x: u32,
+    y: u32,
+    direction: Direction,
+    node: WrappedCorridorNode,
+}
+
+impl MazeAlgorithm for DrunkardsWalk {
+    fn carve(&self, rng: &mut dyn RngCore, mut map: Map, options: &GeneratorOptions) -> Map {
+        let size = (
+            options.corridor_width as u32,
+            options.corridor_height as u32,
+        );
+        let margins = options.margins;
+        let available = map
+            .cell_vector
+            .iter()
+            .filter(|c| **c != Cell::SolidRock)
+            .count();
+        let target = (available as f32 * self.floor_share) as usize;
+
+        let mut tunnels = vec![false; map.cell_vector.len()];
+        let mut dug = 0;
+        // Every position is a corridor piece, walking back over a tunnel
+        // continues from the piece that's already there. Crossing another
+        // tunnel isn't joined in the tree, dead end removal sees it on the map
+        let mut nodes: HashMap<(u32, u32), WrappedCorridorNode> = HashMap::new();
+
+        let mut walkers = vec![];
+        for _ in 0..self.walkers {
+            // Give up on walkers that can't find a place to start
+            for _ in 0..100 {
+                let x = rng.gen_range(0, map.width);
+                let y = rng.gen_range(0, map.height);
+                if !is_diggable(&map, x, y, size, margins) {
+                    continue;
+                }
+                dug += dig(&mut tunnels, map.width, x, y, size);
+                let node = CorridorNode::new(None, x, y);
+                map.corridor_tree.push(Rc::clone(&node));
+                nodes.insert((x, y), Rc::clone(&node));
+                walkers.push(Walker {
+                    x,
+                    y,
+                    direction: Direction::rand(rng),
+                    node,
+                });
+                break;
+            }
+        }
+
+        'walk: for _ in 0..self.steps {
+            for walker in walkers.iter_mut() {
+                if dug >= target {
+                    break 'walk;
+                }
+                if rng.gen::<f32>() < self.turn_chance {
+                    walker.direction = Direction::rand(rng);
+                }
+                let (x, y) = match walker.direction {
+                    Direction::N => (walker.x, walker.y.wrapping_sub(1)),
+                    Direction::E => (walker.x + 1, walker.y),
+                    Direction::S => (walker.x, walker.y + 1),
+                    Direction::W => (walker.x.wrapping_sub(1), walker.y),
+                };
+                if !is_diggable(&map, x, y, size, margins) {
+                    // Bumped into something, try another way next step
+                    walker.direction = Direction::rand(rng);
+                    continue;
+                }
+                dug += dig(&mut tunnels, map.width, x, y, size);
+                let parent = Rc::clone(&walker.node);
+                walker.node = Rc::clone(
+                    nodes
+                        .entry((x, y))
+                        .or_insert_with(|| CorridorNode::new(Some(&parent), x, y)),
+                );
+                walker.x = x;
+                walker.y = y;
+            }
+        }
+
+        add_tunnels(&mut map, &tunnels);
+        return map;
+    }
+}
+
+/**
+ * Turns the dug cells into corridors, tunnels that run into each other
+ * are one corridor with its own section
+ */
+pub(crate) fn add_tunnels(map: &mut Map, tunnels: &[bool]) {
+    let width = map.width as usize;
+    for start in 0..tunnels.len() {
+        if !tunnels[start] || map.cell_vector[start].is_corridor() {
+            continue;
+        }
+        let corridor_index = map.add_corridor();
+        map.cell_vector[start] = Cell::Corridor(corridor_index);
+        let mut stack = vec![start];
+        while let Some(idx) = stack.pop() {
+            let (x, y) = ((idx % width) as u32, (idx / width) as u32);
+            let neighbors: Vec<(u32, u32)> = map.neighbors4(x, y).map(|(x, y, _)| (x, y)).collect();
+            for (n_x, n_y) in neighbors {
+                let n_idx = n_y as usize * width + n_x as usize;
+                if tunnels[n_idx] && !map.cell_vector[n_idx].is_corridor() {
+                    map.cell_vector[n_idx] = Cell::Corridor(corridor_index);
+                    stack.push(n_idx);
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Marks a corridor piece as tunnel, returns how many cells weren't dug before
+ */
+pub(crate) fn dig(tunnels: &mut [bool], width: u32, x: u32, y: u32, size: (u32, u32)) -> usize {
+    let mut dug = 0;
+    for pos_y in y..y + size.1 {
+        for pos_x in x..x + size.0 {
+            let idx = pos_y as usize * width as usize + pos_x as usize;
+            if !tunnels[idx] {
+                tunnels[idx] = true;
+                dug += 1;
+            }
+        }
+    }
+    return dug;
+}
+
+/**
+ * Whether a corridor piece can be dug at the position, it has to be rock
+ * away from the edge of the map shape and the margins around rooms
+ */
+pub(crate) fn is_diggable(map: &Map, x: u32, y: u32, size: (u32, u32), margins: (u8, u8)) -> bool {
+    if x == 0 || y == 0 || x + size.0 >= map.width || y + size.1 >= map.height {
+        return false;
+    }
+    if map
+        .rect_is(x as i32, y as i32, size.0, size.1, |c| !c.is_rock())
+        .is_some()
+    {
+        return false;
+    }
+    // Leaves room for walls along the shape of the map
+    if map
+        .rect_is(x as i32 - 1, y as i32 - 1, size.0 + 2, size.1 + 2, |c| {
+            *c == Cell::SolidRock
+        })
+        .is_some()
+    {
+        return false;
+    }
+    return map
+        .rect_is(
+            x as i32 - margins.0 as i32,
+            y as i32 - margins.1 as i32,
+            size.0 + 2 * margins.0 as u32,
+            size.1 + 2 * margins.1 as u32,
+            |c| c.is_room(),
+        )
+        .is_none();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cave::regions;
+    use crate::map_generator::{Generator, Layout};
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /**
+     * How many groups of walkable cells the map has
+     */
+    fn region_count(map: &Map) -> usize {
+        let rock: Vec<bool> = map.cell_vector.iter().map(|c| !c.is_walkable()).collect();
+        return regions(&rock, (map.width as i32, map.height as i32)).len();
+    }
+
+    #[test]
+    fn walkers_stop_at_the_floor_share() {
+        let walk = DrunkardsWalk {
+            steps: 100_000,
+            floor_share: 0.2,
+            ..DrunkardsWalk::default()
+        };
+        let options = Generator::new().corridor_size(1, 1).options;
+        for seed in 0..10 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let map = walk.carve(&mut rng, Map::new(60, 40, Cell::Rock), &options);
+            let floor = map.iter().filter(|(_, _, c)| c.is_corridor()).count();
+            // The walkers start digging before the target is checked
+            assert!((480..484).contains(&floor), "{} seed {}", floor, seed);
+            for (x, y, cell) in map.iter() {
+                if x == 0 || y == 0 || x == 59 || y == 39 {
+                    assert!(cell.is_rock());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tunnel_maps_are_one_region() {
+        for seed in 0..10 {
+            let map = Generator::new()
+                .seed(seed)
+                .layout(Layout::DrunkardsWalk(DrunkardsWalk::default()))
+                .generate();
+            assert_eq!(map.iter_rooms().len(), 0);
+            assert_eq!(region_count(&map), 1, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn tunnels_between_rooms_are_one_region() {
+        for seed in 0..10 {
+            let map = Generator::new()
+                .seed(seed)
+                .maze_algorithm(DrunkardsWalk::default())
+                .generate();
+            assert!(map.iter_rooms().len() > 0);
+            assert_eq!(region_count(&map), 1, "seed {}", seed);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+#[derive(Clone, Debug, PartialEq)]
+pub enum GenerateError {
+    // The map has a width or height of 0
+    EmptyMap,
+    // The minimum room size is 0 or larger than the maximum room size
+    InvalidRoomSize {
+        min: (u32, u32),
+        max: (u32, u32),
+    },
+    // There are no room shapes to pick from
+    NoRoomShapes,
+    // The maximum room size doesn't fit inside the map, (width, height)
+    RoomLargerThanMap {
+        room: (u32, u32),
+        map: (u32, u32),
+    },
+    // The standard deviation of the normal room size distribution is below 0
+    InvalidRoomDeviation(f32),
+    // There are no room size classes with a weight to pick from
+    NoRoomSizeClasses,
+    // The maximum aspect ratio is below 1 or the room sizes can't have it
+    InvalidAspectRatio(f32),
+    // The minimum room count is larger than the maximum
+    InvalidRoomCount {
+        min: u32,
+        max: u32,
+    },
+    // The room coverage isn't inside 0-1
+    InvalidRoomCoverage(f32),
+    // The rooms didn't reach the minimum room count or coverage,
+    // the last attempt had this many rooms covering this share of the map
+    RoomTargetsMissed {
+        rooms: usize,
+        coverage: f32,
+    },
+    // A BSP leaf size is 0 or the split ratio isn't a range inside 0-1
+    InvalidBsp {
+        min_leaf: (u32, u32),
+        split_ratio: (f32, f32),
+    },
+    // A fixed room doesn't fit inside the map, (x, y, width, height) and (width, height)
+    FixedRoomOutsideMap {
+        room: (u32, u32, u32, u32),
+        map: (u32, u32),
+    },
+    // A fixed room covers a reserved area, another fixed room
+    // or the outside of the map shape, (x, y, width, height)
+    FixedRoomBlocked {
+        room: (u32, u32, u32, u32),
+    },
+    // No corridor could be carved to a fixed room, (x, y, width, height)
+    FixedRoomUnreachable {
+        room: (u32, u32, u32, u32),
+    },
+    // A reserved mask doesn't have a cell for every position of its size
+    InvalidReservedMask,
+    // The map shape mask doesn't have a cell for every position of its size
+    InvalidShapeMask,
+    // The corridor has a width or height of 0
+    InvalidCorridorSize,
+    // The corridor doesn't fit inside the map, (width, height)
+    CorridorLargerThanMap {
+        corridor: (u8, u8),
+        map: (u32, u32),
+    },
+    // A margin is 0, (horizontal, vertical)
+    InvalidMargins((u8, u8)),
+    // The door width is 0
+    InvalidDoorWidth,
+    // Rotational4 symmetry needs a map with the same width and height
+    SymmetryRequiresSquareMap,
+    // No corridor could be carved between the mirrored copies of the map
+    SymmetryDisconnected,
+    // Neither rooms nor corridors could be placed on the map, or every
+    // corridor of a map without rooms was removed as a dead end
+    NoSections,
+}
+
+impl std::fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GenerateError::EmptyMap => write!(f, "Map width and height must be greater than 0"),
+            GenerateError::InvalidRoomSize { min, max } => write!(
+                f,
+                "Invalid room size, min {:?} must be greater than 0 and not larger than max {:?}",
+                min, max
+            ),
+            GenerateError::NoRoomShapes => write!(f, "At least one room shape is required"),
+            GenerateError::RoomLargerThanMap { room, map } => write!(
+                f,
+                "Room size {:?} is larger than the map size {:?}",
+                room, map
+            ),
+            GenerateError::InvalidRoomDeviation(deviation) => write!(
+                f,
+                "Room size deviation {} must not be negative",
+                deviation
+            ),
+            GenerateError::NoRoomSizeClasses => {
+                write!(f, "At least one room size class with a weight is required")
+            }
+            GenerateError::InvalidAspectRatio(ratio) => {
+                write!(
+                f,
+                "Aspect ratio {} must be at least 1 and possible with the room sizes",
+                ratio
+            )
+            }
+            GenerateError::InvalidRoomCount { min, max } => write!(
+                f,
+                "Invalid room count, min {} must not be larger than max {}",
+                min, max
+            ),
+            GenerateError::InvalidRoomCoverage(coverage) => {
+                write!(f, "Room coverage {} must be inside 0-1", coverage)
+            }
+            GenerateError::RoomTargetsMissed { rooms, coverage } => write!(
+                f,
+                "Only {} rooms covering {:.2} of the map could be placed",
+                rooms, coverage
+            ),
+            GenerateError::InvalidBsp {
+                min_leaf,
+                split_ratio,
+            } => write!(
+                f,
+                "Invalid BSP, min leaf {:?} must be greater than 0 and split ratio {:?} must be a range inside 0-1",
+                min_leaf, split_ratio
+            ),
+            GenerateError::FixedRoomOutsideMap { room, map } => write!(
+                f,
+                "Fixed room {:?} is outside of the map size {:?}",
+                room, map
+            ),
+            GenerateError::FixedRoomBlocked { room } => write!(
+                f,
+                "Fixed room {:?} isn't on rock inside of the map shape",
+                room
+            ),
+            GenerateError::FixedRoomUnreachable { room } => write!(
+                f,
+                "Fixed room {:?} could not be connected to the rest of the map",
+                room
+            ),
+            GenerateError::InvalidReservedMask => write!(
+                f,
+                "Reserved masks need a cell for every position of their size"
+            ),
+            GenerateError::InvalidShapeMask => write!(
+                f,
+                "Map shape masks need a cell for every position of their size"
+            ),
+            GenerateError::InvalidCorridorSize => {
+                write!(f, "Corridor width and height must be greater than 0")
+            }
+            GenerateError::CorridorLargerThanMap { corridor, map } => write!(
+                f,
+                "Corridor size {:?} does not fit inside the map size {:?}",
+                corridor, map
+            ),
+            GenerateError::InvalidMargins(margins) => {
+                write!(f, "Margins {:?} must be greater than 0", margins)
+            }
+            GenerateError::InvalidDoorWidth => write!(f, "Door width must be greater than 0"),
+            GenerateError::SymmetryRequiresSquareMap => {
+                write!(f, "Four way rotational symmetry requires a square map")
+            }
+            GenerateError::SymmetryDisconnected => {
+                write!(f, "The mirrored parts of the map could not be connected")
+            }
+            GenerateError::NoSections => write!(f, "No rooms or corridors could be placed"),
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PrefabParseError {
+    // The template has no floor cells
+    Empty,
+    // A character other than `.`, `D`, `#` or whitespace, (x, y) in the template
+    UnknownGlyph { x: u32, y: u32, glyph: char },
+    // A door socket isn't next to rock, (x, y) in the template
+    SocketNotOnEdge { x: u32, y: u32 },
+}
+
+impl std::fmt::Display for PrefabParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PrefabParseError::Empty => {
+                write!(f, "A prefab template needs at least one floor cell")
+            }
+            PrefabParseError::UnknownGlyph { x, y, glyph } => write!(
+                f,
+                "Unknown character {:?} at ({}, {}) in the prefab template",
+                glyph, x, y
+            ),
+            PrefabParseError::SocketNotOnEdge { x, y } => write!(
+                f,
+                "Door socket at ({}, {}) in the prefab template isn't on the edge of the room",
+                x, y
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PrefabParseError {}
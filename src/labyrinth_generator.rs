@@ -1,97 +1,86 @@
 use crate::cell_matrix::{Cell, Map};
-use crate::corridor_tree::{remove_node, CorridorNode, WrappedCorridorNode};
-use crate::room::Corridor;
-use crate::sections::Section;
+use crate::corridor_tree::{CorridorNode, WrappedCorridorNode};
 
-use rand::prelude::ThreadRng;
-use rand::thread_rng;
 use rand::Rng;
 
 use crate::direction::Direction;
 
-pub struct LabyrinthGenerator {
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+pub struct LabyrinthGenerator<'a, R: Rng + ?Sized> {
     map: Map,
     corridor_width: u8,
     corridor_height: u8,
     corridor_errantness: f32,
     margins: (u8, u8),
-    rng: ThreadRng,
+    rng: &'a mut R,
+    // Where new corridors can start, for every row the suitable x positions.
+    // Carving only ever makes locations unsuitable, so locations are
+    // removed as corridor pieces are added
+    start_locations: Vec<BTreeSet<u32>>,
 }
 
-impl LabyrinthGenerator {
+impl<'a, R: Rng + ?Sized> LabyrinthGenerator<'a, R> {
     pub fn new(
+        rng: &'a mut R,
         map: Map,
         corridor_width: u8,
         corridor_height: u8,
         corridor_errantness: f32,
         margins: (u8, u8),
-    ) -> LabyrinthGenerator {
+    ) -> LabyrinthGenerator<'a, R> {
         return LabyrinthGenerator {
             map,
             corridor_width,
             corridor_height,
             corridor_errantness,
             margins,
-            rng: thread_rng(),
+            rng,
+            start_locations: vec![],
         };
     }
     pub fn generate(mut self) -> Map {
+        self.start_locations = (0..self.map.height)
+            .map(|y| {
+                (0..self.map.width)
+                    .filter(|x| self.is_suitable_start(*x, y))
+                    .collect()
+            })
+            .collect();
         // let mut corridor_vector: Vec<Corridor> = vec![];
         let mut root_nodes: Vec<WrappedCorridorNode> = vec![];
         'suitable: loop {
             match self.find_suitable_corridor_location() {
                 Ok((x, y)) => {
                     let idx = self.map.add_corridor();
-                    root_nodes.push(self.traverse_corridor(x, y, Direction::rand(), idx, None));
+                    let direction = Direction::rand(self.rng);
+                    root_nodes.push(self.traverse_corridor(x, y, direction, idx, None));
                 }
                 Err(_) => break 'suitable,
             };
         }
-        // print tree
-        /* for root_node in root_nodes.clone() {
-            println!("--------------------------------");
-            print!("({}, {})\n", root_node.borrow().x, root_node.borrow().y);
-            self.print_tree_children(&root_node);
-        }*/
         // Remove all corridors that are too small
         // self.prune_node_tree(root_nodes);
         self.map.corridor_tree = root_nodes;
         return self.map;
     }
 
-    fn print_tree_children(&self, node: &WrappedCorridorNode) {
-        for child in &node.borrow().children {
-            println!("˅˅˅˅˅˅˅˅˅˅˅˅˅˅˅˅");
-            print!("Child({}, {}) ", child.borrow().x, child.borrow().y);
-            self.print_tree_children(child);
-            println!("˄˄˄˄˄˄˄˄˄˄˄˄˄˄˄˄");
-        }
-    }
-
-    fn find_suitable_corridor_location(&mut self) -> Result<(u16, u16), String> {
+    fn find_suitable_corridor_location(&mut self) -> Result<(u32, u32), String> {
         let start_x = self.rng.gen_range(0, self.map.width);
         let start_y = self.rng.gen_range(0, self.map.height);
 
-        for y in 0..(self.map.height - (1 + self.corridor_height) as u16) {
-            for x in 0..(self.map.width - (1 + self.corridor_width) as u16) {
-                let x_pos = (x + start_x) % self.map.width;
-                let y_pos = (y + start_y) % self.map.height;
-
-                if is_suitable_corridor_location(
-                    &self.map,
-                    x_pos as i32,
-                    y_pos as i32,
-                    self.corridor_width,
-                    self.corridor_width,
-                    (
-                        self.margins.0,
-                        self.margins.1,
-                        self.margins.0,
-                        self.margins.1,
-                    ),
-                ) {
-                    return Ok((x_pos, y_pos));
-                }
+        // Go through the rows from the random start, wrapping around the map
+        let end_x = start_x + self.map.width - (1 + self.corridor_width) as u32;
+        for y in 0..(self.map.height - (1 + self.corridor_height) as u32) {
+            let y_pos = (y + start_y) % self.map.height;
+            let row = &self.start_locations[y_pos as usize];
+            let found = row
+                .range(start_x..end_x.min(self.map.width))
+                .next()
+                .or_else(|| row.range(0..end_x.saturating_sub(self.map.width)).next());
+            if let Some(x_pos) = found {
+                return Ok((*x_pos, y_pos));
             }
         }
         return Err(String::from(
@@ -99,133 +88,227 @@ impl LabyrinthGenerator {
         ));
     }
 
+    fn is_suitable_start(&self, x: u32, y: u32) -> bool {
+        return is_suitable_corridor_location(
+            &self.map,
+            x as i32,
+            y as i32,
+            self.corridor_width,
+            self.corridor_height,
+            (
+                self.margins.0,
+                self.margins.1,
+                self.margins.0,
+                self.margins.1,
+            ),
+        );
+    }
+
+    /**
+     * Removes the start locations whose margins overlap the rectangle
+     */
+    fn remove_start_locations(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        let size = (self.corridor_width as u32, self.corridor_height as u32);
+        let (margin_x, margin_y) = (self.margins.0 as u32, self.margins.1 as u32);
+        let first_y = (y + 1).saturating_sub(size.1 + margin_y);
+        let last_y = (y + height + margin_y).min(self.map.height);
+        let first_x = (x + 1).saturating_sub(size.0 + margin_x);
+        let last_x = (x + width + margin_x).min(self.map.width);
+        for row in &mut self.start_locations[first_y as usize..last_y as usize] {
+            let removed: Vec<u32> = row.range(first_x..last_x).cloned().collect();
+            for pos_x in removed {
+                row.remove(&pos_x);
+            }
+        }
+    }
+
     fn add_corridor_piece(
         &mut self,
         cell: Cell,
-        x: u16,
-        y: u16,
+        x: u32,
+        y: u32,
         parent: Option<&WrappedCorridorNode>,
     ) -> WrappedCorridorNode {
-        self.map.set_rect(
-            cell,
+        let (width, height) = (self.corridor_width as u32, self.corridor_height as u32);
+        self.map.set_rect(cell, x, y, width, height);
+        self.remove_start_locations(x, y, width, height);
+        return CorridorNode::new(parent, x, y);
+    }
+    /**
+     * Carves a piece at the location and sets up the directions the
+     * corridor can continue in from there
+     */
+    fn enter_piece(
+        &mut self,
+        x: u32,
+        y: u32,
+        direction: Direction,
+        corridor_index: usize,
+        parent: Option<&WrappedCorridorNode>,
+    ) -> CarveStep {
+        let direction = match self.rng.gen::<f32>() {
+            x if x > self.corridor_errantness => Direction::rand(self.rng),
+            _ => direction,
+        };
+        let node = self.add_corridor_piece(Cell::Corridor(corridor_index), x, y, parent);
+        return CarveStep {
+            node,
+            x,
+            y,
+            direction,
+            direction_pool: vec![Direction::E, Direction::N, Direction::S, Direction::W],
+            tries: 0,
+            tried: false,
+        };
+    }
+
+    fn next_location(&self, x: u32, y: u32, direction: Direction) -> Option<(u32, u32)> {
+        return next_corridor_location(
+            &self.map,
             x,
             y,
-            self.corridor_width as u16,
-            self.corridor_height as u16,
+            direction,
+            (self.corridor_width, self.corridor_height),
+            self.margins,
         );
-        return CorridorNode::new(parent, x, y);
     }
+
     /**
-     * Recursive corridor logic
+     * Carves a corridor depth first from the location, every piece tries
+     * three directions before the corridor backtracks. The pieces being
+     * carved are kept on a stack as corridors can be very long
      */
     fn traverse_corridor(
         &mut self,
-        x: u16,
-        y: u16,
+        x: u32,
+        y: u32,
         // horizontal / vertical
         direction: Direction,
         corridor_index: usize,
         parent: Option<&WrappedCorridorNode>,
     ) -> WrappedCorridorNode {
-        let mut direction = match thread_rng().gen::<f32>() {
-            x if x > self.corridor_errantness => Direction::rand(),
-            _ => direction,
-        };
-        // Start the labyrinth algorithm here
-        // This just sets a corridor piece at the starting location
-        let node = self.add_corridor_piece(Cell::Corridor(corridor_index), x, y, parent);
-
-        let mut direction_pool = vec![Direction::E, Direction::N, Direction::S, Direction::W];
-        for _ in 0..3 {
-            match direction {
-                Direction::N => {
-                    if is_suitable_corridor_location(
-                        &self.map,
-                        x as i32,
-                        y as i32 - 1,
-                        self.corridor_width,
-                        1,
-                        (self.margins.0, self.margins.1, self.margins.0, 0),
-                    ) {
-                        self.traverse_corridor(
-                            x,
-                            y - 1,
-                            direction.clone(),
-                            corridor_index,
-                            Some(&node),
-                        );
-                    }
-                }
-                Direction::E => {
-                    let x_new = x + self.corridor_width as u16;
-                    if is_suitable_corridor_location(
-                        &self.map,
-                        x_new as i32,
-                        y as i32,
-                        1,
-                        self.corridor_height,
-                        (0, self.margins.1, self.margins.0, self.margins.1),
-                    ) {
-                        self.traverse_corridor(
-                            x + 1,
-                            y,
-                            direction.clone(),
-                            corridor_index,
-                            Some(&node),
-                        );
-                    }
-                }
-                Direction::S => {
-                    let y_new = y + self.corridor_height as u16;
-                    if is_suitable_corridor_location(
-                        &self.map,
-                        x as i32,
-                        y_new as i32,
-                        self.corridor_width,
-                        1,
-                        (self.margins.0, 0, self.margins.0, self.margins.1),
-                    ) {
-                        self.traverse_corridor(
-                            x,
-                            y + 1,
-                            direction.clone(),
-                            corridor_index,
-                            Some(&node),
-                        );
-                    }
-                }
-                Direction::W => {
-                    if is_suitable_corridor_location(
-                        &self.map,
-                        x as i32 - 1,
-                        y as i32,
-                        1,
-                        self.corridor_height,
-                        (self.margins.0, self.margins.1, 0, self.margins.1),
-                    ) {
-                        self.traverse_corridor(
-                            x - 1,
-                            y,
-                            direction.clone(),
-                            corridor_index,
-                            Some(&node),
-                        );
-                    }
+        let first = self.enter_piece(x, y, direction, corridor_index, parent);
+        let root = Rc::clone(&first.node);
+        let mut stack = vec![first];
+        while let Some(step) = stack.last_mut() {
+            if step.tries == 3 {
+                stack.pop();
+                continue;
+            }
+            let (x, y, direction) = (step.x, step.y, step.direction);
+            if !step.tried {
+                // Continue the corridor before trying the next direction
+                step.tried = true;
+                if let Some((x_new, y_new)) = self.next_location(x, y, direction) {
+                    let node = Rc::clone(&step.node);
+                    let next =
+                        self.enter_piece(x_new, y_new, direction, corridor_index, Some(&node));
+                    stack.push(next);
                 }
+                continue;
             }
-            let idx = direction_pool.iter().position(|d| *d == direction).unwrap();
-            direction_pool.remove(idx);
-            if direction_pool.len() == 1 {
-                direction = direction_pool[0];
+            let idx = step
+                .direction_pool
+                .iter()
+                .position(|d| *d == direction)
+                .unwrap();
+            step.direction_pool.remove(idx);
+            step.direction = if step.direction_pool.len() == 1 {
+                step.direction_pool[0]
             } else {
-                direction = direction_pool[self.rng.gen_range(0, direction_pool.len()) as usize];
+                step.direction_pool[self.rng.gen_range(0, step.direction_pool.len())]
+            };
+            step.tries += 1;
+            step.tried = false;
+        }
+        return root;
+    }
+}
+
+// A corridor piece that is being carved
+struct CarveStep {
+    node: WrappedCorridorNode,
+    x: u32,
+    y: u32,
+    // The direction that is tried next
+    direction: Direction,
+    // The directions that haven't been tried yet
+    direction_pool: Vec<Direction>,
+    // How many directions have been tried
+    tries: u8,
+    // Whether the current direction has been tried
+    tried: bool,
+}
+
+/**
+ * The location a corridor continues at from the piece at x, y in the direction,
+ * if there's room for it
+ */
+pub(crate) fn next_corridor_location(
+    map: &Map,
+    x: u32,
+    y: u32,
+    direction: Direction,
+    // (width, height)
+    corridor_size: (u8, u8),
+    margins: (u8, u8),
+) -> Option<(u32, u32)> {
+    match direction {
+        Direction::N => {
+            if is_suitable_corridor_location(
+                map,
+                x as i32,
+                y as i32 - 1,
+                corridor_size.0,
+                1,
+                (margins.0, margins.1, margins.0, 0),
+            ) {
+                return Some((x, y - 1));
+            }
+        }
+        Direction::E => {
+            let x_new = x + corridor_size.0 as u32;
+            if is_suitable_corridor_location(
+                map,
+                x_new as i32,
+                y as i32,
+                1,
+                corridor_size.1,
+                (0, margins.1, margins.0, margins.1),
+            ) {
+                return Some((x + 1, y));
+            }
+        }
+        Direction::S => {
+            let y_new = y + corridor_size.1 as u32;
+            if is_suitable_corridor_location(
+                map,
+                x as i32,
+                y_new as i32,
+                corridor_size.0,
+                1,
+                (margins.0, 0, margins.0, margins.1),
+            ) {
+                return Some((x, y + 1));
+            }
+        }
+        Direction::W => {
+            if is_suitable_corridor_location(
+                map,
+                x as i32 - 1,
+                y as i32,
+                1,
+                corridor_size.1,
+                (margins.0, margins.1, 0, margins.1),
+            ) {
+                return Some((x - 1, y));
             }
         }
-        return node;
     }
+    return None;
 }
 
-fn is_suitable_corridor_location(
+pub(crate) fn is_suitable_corridor_location(
     map: &Map,
     x: i32,
     y: i32,
@@ -235,23 +318,39 @@ fn is_suitable_corridor_location(
     // left, top, right, bottom
     margins: (u8, u8, u8, u8),
 ) -> bool {
-    let margin_rect = map.get_rect(
-        x - margins.0 as i32,
-        y - margins.1 as i32,
-        width as u16 + (margins.2 + margins.0) as u16,
-        height as u16 + (margins.3 + margins.1) as u16,
-    );
-    if margin_rect
-        .cell_vector
-        .iter()
-        .any(|c| *c != Cell::SolidRock && !c.is_rock())
+    // Solid rock is fine in the margins but not where the corridor goes
+    if map
+        .rect_is(
+            x - margins.0 as i32,
+            y - margins.1 as i32,
+            width as u32 + margins.2 as u32 + margins.0 as u32,
+            height as u32 + margins.3 as u32 + margins.1 as u32,
+            |c| *c != Cell::SolidRock && !c.is_rock(),
+        )
+        .is_some()
     {
         return false;
     }
-    // Check the inner rectangle as well
-    let cell_rect = map.get_rect(x, y, width as u16, height as u16);
-    if cell_rect.cell_vector.iter().any(|c| !c.is_rock()) {
-        return false;
+    return map
+        .rect_is(x, y, width as u32, height as u32, |c| !c.is_rock())
+        .is_none();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map_generator::Generator;
+
+    #[test]
+    fn non_square_corridors() {
+        for (width, height) in [(1, 3), (3, 1), (2, 3)].iter() {
+            for seed in 0..20 {
+                let map = Generator::new()
+                    .seed(seed)
+                    .size(40, 40)
+                    .corridor_size(*width, *height)
+                    .try_generate();
+                assert!(map.is_ok());
+            }
+        }
     }
-    return true;
 }
@@ -1,13 +1,56 @@
+// The codebase prefers explicit returns and `match` over `if let`
+#![allow(
+    clippy::needless_return,
+    clippy::single_match,
+    clippy::match_like_matches_macro
+)]
+
 extern crate rand;
 
+mod bsp;
+mod cave;
 mod cell_matrix;
+mod connectivity;
 mod corridor_tree;
+mod dead_ends;
 mod direction;
+mod division;
+mod doors;
+mod drunkards_walk;
+mod error;
 mod labyrinth_generator;
 mod map_generator;
+mod map_shape;
+mod maze;
+mod prefab;
+mod reserved;
 mod room;
 mod room_generator;
 mod sections;
+mod separation;
+mod symmetry;
+mod walls;
 
+pub use crate::bsp::Bsp;
+pub use crate::cave::Cave;
 pub use crate::cell_matrix::{Cell, Map};
-pub use crate::map_generator::Generator;
+pub use crate::connectivity::DisconnectedPolicy;
+pub use crate::dead_ends::DeadEndRemoval;
+pub use crate::direction::{Direction, Orientation};
+pub use crate::division::RecursiveDivision;
+pub use crate::doors::DoorKind;
+pub use crate::drunkards_walk::DrunkardsWalk;
+pub use crate::error::{GenerateError, PrefabParseError};
+pub use crate::map_generator::{Generator, GeneratorOptions, Layout, RoomPlacement};
+pub use crate::map_shape::MapShape;
+pub use crate::maze::{
+    AldousBroder, BinaryTree, Eller, GrowingTree, GrowingTreeSelection, HuntAndKill, Kruskal,
+    MazeAlgorithm, Prim, RecursiveBacktracker, Sidewinder, Wilson,
+};
+pub use crate::prefab::Prefab;
+pub use crate::reserved::ReservedArea;
+pub use crate::room::{Room, RoomShape, RoomSizeDistribution, SizeClass};
+pub use crate::sections::Connection;
+pub use crate::separation::Separation;
+pub use crate::symmetry::Symmetry;
+pub use crate::walls::{Corner, WallCorner};
@@ -1,21 +1,81 @@
+use crate::bsp::Bsp;
+use crate::cave::{generate_caves, Cave};
 use crate::cell_matrix::{Cell, Map};
-use crate::labyrinth_generator::LabyrinthGenerator;
-use crate::room_generator::generate_rooms;
+use crate::connectivity::DisconnectedPolicy;
+use crate::dead_ends::DeadEndRemoval;
+use crate::division::{divide, RecursiveDivision};
+use crate::doors::DoorKind;
+use crate::drunkards_walk::DrunkardsWalk;
+use crate::error::GenerateError;
+use crate::map_shape::MapShape;
+use crate::maze::{MazeAlgorithm, RecursiveBacktracker};
+use crate::prefab::Prefab;
+use crate::reserved::ReservedArea;
+use crate::room::{RoomShape, RoomSizeDistribution};
+use crate::room_generator::{allows_aspect_ratio, generate_rooms};
 use crate::sections::SectionMerger;
+use crate::separation::Separation;
+use crate::symmetry::Symmetry;
+use crate::walls::place_walls;
 
-pub enum MapShape {
-    Square,
-    Circle,
-    Custom(&'static FnOnce() -> String),
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+
+// How many times the rooms are placed before the room targets are given up on
+const ROOM_ATTEMPTS: u32 = 8;
+
+pub enum Layout {
+    // Rooms in the rock connected by corridors
+    Dungeon,
+    // Walls put into an open area, rooms are areas that weren't split
+    RecursiveDivision(RecursiveDivision),
+    // Caverns grown in the rock, every cavern is a room
+    Cave(Cave),
+    // Tunnels dug through the rock by random walkers, without rooms
+    DrunkardsWalk(DrunkardsWalk),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoomPlacement {
+    // Rooms are put at random positions, `iterations` times
+    Random,
+    // Every leaf of a binary space partition gets a room, the rooms are joined
+    // through the partition instead of with the maze algorithm
+    Bsp(Bsp),
+    // A set number of rooms scattered around the center and pushed apart
+    Separation(Separation),
 }
 
 pub struct GeneratorOptions {
-    pub width: u16,
-    pub height: u16,
+    pub layout: Layout,
+    pub width: u32,
+    pub height: u32,
     // (width, height)
-    pub room_min: (u16, u16),
+    pub room_min: (u32, u32),
     // (width, height)
-    pub room_max: (u16, u16),
+    pub room_max: (u32, u32),
+    // How the room sizes are picked
+    pub room_size_distribution: RoomSizeDistribution,
+    // Rooms are at most this many times longer than they are wide
+    pub max_aspect_ratio: Option<f32>,
+    // Every room gets a random shape from this list
+    pub room_shapes: Vec<RoomShape>,
+    // How the rooms are spread over the map
+    pub room_placement: RoomPlacement,
+    // Rooms are placed until there are at least this many
+    pub min_rooms: u32,
+    // No more rooms are placed once there are this many
+    pub max_rooms: Option<u32>,
+    // 0-1, rooms are placed until they cover this share of the map
+    pub room_coverage: f32,
+    // Hand made rooms placed between the generated rooms
+    pub prefabs: Vec<Prefab>,
+    // 0-1, chance that a room is a prefab instead of a generated room
+    pub prefab_chance: f32,
+    // (x, y, width, height), rooms stamped before the other rooms are placed
+    pub fixed_rooms: Vec<(u32, u32, u32, u32)>,
+    // Parts of the map that stay solid rock
+    pub reserved: Vec<ReservedArea>,
     // pub wall_height: u8,
     pub iterations: u32,
     pub shape: MapShape,
@@ -27,33 +87,258 @@ pub struct GeneratorOptions {
     pub margins: (u8, u8),
     // 0-1
     pub corridor_errantness: f32,
+    // How the corridors between the rooms are carved
+    pub maze_algorithm: Box<dyn MazeAlgorithm>,
     // How long a corridor has to be to be considered for pruning
     pub prune_length: u32,
+    pub dead_end_removal: DeadEndRemoval,
+    // What happens to the parts of the map that can't be reached
+    pub disconnected_policy: DisconnectedPolicy,
+    // How many cells wide doors are
+    pub door_width: u8,
+    // The kinds of doors placed between rooms and the rest of the map,
+    // with how often each kind is picked. No doors are placed if it's empty
+    pub door_kinds: Vec<(DoorKind, u32)>,
+    // 0-1, chance that an unused connection is opened to create a loop
+    pub extra_connection_chance: f32,
+    // How many steps the walk between the two sides of a loop
+    // connection has to be at least
+    pub loop_min_distance: u32,
+    // 0-1, share of the unused connections that are turned into secret passages
+    pub secret_passage_share: f32,
+    pub symmetry: Symmetry,
+    // Surround the walkable cells with walls
+    pub walls: bool,
+    // How many cells around the walls are marked as perimeter
+    pub perimeter: u8,
+}
+
+impl GeneratorOptions {
+    /**
+     * Checks that the options can generate a map
+     */
+    pub fn validate(&self) -> Result<(), GenerateError> {
+        if self.symmetry == Symmetry::Rotational4 && self.width != self.height {
+            return Err(GenerateError::SymmetryRequiresSquareMap);
+        }
+        // Symmetric maps are only generated in a part of the map
+        let (width, height) = self.symmetry.domain_size(self.width, self.height);
+        if width == 0 || height == 0 {
+            return Err(GenerateError::EmptyMap);
+        }
+        // Only dungeons place rooms from the room options
+        let has_rooms = match self.layout {
+            Layout::Dungeon => true,
+            _ => false,
+        };
+        if has_rooms
+            && (self.room_min.0 == 0
+                || self.room_min.1 == 0
+                || self.room_min.0 > self.room_max.0
+                || self.room_min.1 > self.room_max.1)
+        {
+            return Err(GenerateError::InvalidRoomSize {
+                min: self.room_min,
+                max: self.room_max,
+            });
+        }
+        if has_rooms && self.room_shapes.is_empty() {
+            return Err(GenerateError::NoRoomShapes);
+        }
+        match &self.room_size_distribution {
+            RoomSizeDistribution::Normal(deviation)
+                if has_rooms && (deviation.is_nan() || *deviation < 0f32) =>
+            {
+                return Err(GenerateError::InvalidRoomDeviation(*deviation));
+            }
+            RoomSizeDistribution::Weighted(classes) if has_rooms => {
+                if classes.iter().map(|(_, weight)| weight).sum::<u32>() == 0 {
+                    return Err(GenerateError::NoRoomSizeClasses);
+                }
+                for (class, _) in classes {
+                    if class.min.0 == 0
+                        || class.min.1 == 0
+                        || class.min.0 > class.max.0
+                        || class.min.1 > class.max.1
+                    {
+                        return Err(GenerateError::InvalidRoomSize {
+                            min: class.min,
+                            max: class.max,
+                        });
+                    }
+                    if class.max.0 > width || class.max.1 > height {
+                        return Err(GenerateError::RoomLargerThanMap {
+                            room: class.max,
+                            map: (width, height),
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+        match self.max_aspect_ratio {
+            Some(ratio) if has_rooms && (ratio.is_nan() || ratio < 1f32) => {
+                return Err(GenerateError::InvalidAspectRatio(ratio));
+            }
+            Some(ratio) if has_rooms => {
+                let allowed = match &self.room_size_distribution {
+                    RoomSizeDistribution::Weighted(classes) => classes
+                        .iter()
+                        .all(|(class, _)| allows_aspect_ratio(class.min, class.max, ratio)),
+                    _ => allows_aspect_ratio(self.room_min, self.room_max, ratio),
+                };
+                if !allowed {
+                    return Err(GenerateError::InvalidAspectRatio(ratio));
+                }
+            }
+            _ => {}
+        }
+        match self.max_rooms {
+            Some(max_rooms) if has_rooms && self.min_rooms > max_rooms => {
+                return Err(GenerateError::InvalidRoomCount {
+                    min: self.min_rooms,
+                    max: max_rooms,
+                });
+            }
+            _ => {}
+        }
+        if has_rooms && !(0f32..=1f32).contains(&self.room_coverage) {
+            return Err(GenerateError::InvalidRoomCoverage(self.room_coverage));
+        }
+        match self.room_placement {
+            RoomPlacement::Bsp(bsp) if has_rooms && !bsp.is_valid() => {
+                return Err(GenerateError::InvalidBsp {
+                    min_leaf: bsp.min_leaf,
+                    split_ratio: bsp.split_ratio,
+                });
+            }
+            _ => {}
+        }
+        for prefab in self.prefabs.iter() {
+            let (prefab_width, prefab_height) = prefab.size();
+            if has_rooms && (prefab_width > width || prefab_height > height) {
+                return Err(GenerateError::RoomLargerThanMap {
+                    room: prefab.size(),
+                    map: (width, height),
+                });
+            }
+        }
+        for (x, y, room_width, room_height) in self.fixed_rooms.iter() {
+            if has_rooms && (*room_width == 0 || *room_height == 0) {
+                return Err(GenerateError::InvalidRoomSize {
+                    min: (*room_width, *room_height),
+                    max: (*room_width, *room_height),
+                });
+            }
+            // Written so that positions close to u32::MAX can't overflow
+            if has_rooms
+                && (*room_width > width
+                    || *x > width - room_width
+                    || *room_height > height
+                    || *y > height - room_height)
+            {
+                return Err(GenerateError::FixedRoomOutsideMap {
+                    room: (*x, *y, *room_width, *room_height),
+                    map: (width, height),
+                });
+            }
+        }
+        if !self.shape.is_valid() {
+            return Err(GenerateError::InvalidShapeMask);
+        }
+        if self.reserved.iter().any(|area| !area.is_valid()) {
+            return Err(GenerateError::InvalidReservedMask);
+        }
+        if has_rooms && (self.room_max.0 > width || self.room_max.1 > height) {
+            return Err(GenerateError::RoomLargerThanMap {
+                room: self.room_max,
+                map: (width, height),
+            });
+        }
+        if self.corridor_width == 0 || self.corridor_height == 0 {
+            return Err(GenerateError::InvalidCorridorSize);
+        }
+        // The corridor needs at least one cell of rock next to it
+        if self.corridor_width as u32 + 1 > width || self.corridor_height as u32 + 1 > height {
+            return Err(GenerateError::CorridorLargerThanMap {
+                corridor: (self.corridor_width, self.corridor_height),
+                map: (width, height),
+            });
+        }
+        if self.margins.0 == 0 || self.margins.1 == 0 {
+            return Err(GenerateError::InvalidMargins(self.margins));
+        }
+        if self.door_width == 0 {
+            return Err(GenerateError::InvalidDoorWidth);
+        }
+        return Ok(());
+    }
 }
 
 pub struct Generator {
-    options: GeneratorOptions,
+    pub(crate) options: GeneratorOptions,
+    seed: Option<u64>,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        return Generator::new();
+    }
 }
 
 impl Generator {
     pub fn new() -> Generator {
         return Generator {
             options: GeneratorOptions {
+                layout: Layout::Dungeon,
                 width: 64,
                 height: 32,
                 room_min: (4, 4),
                 room_max: (8, 8),
+                room_size_distribution: RoomSizeDistribution::Uniform,
+                max_aspect_ratio: None,
+                room_shapes: vec![RoomShape::Rectangle],
+                room_placement: RoomPlacement::Random,
+                min_rooms: 0,
+                max_rooms: None,
+                room_coverage: 0f32,
+                prefabs: vec![],
+                prefab_chance: 0.2,
+                fixed_rooms: vec![],
+                reserved: vec![],
                 iterations: 64,
                 shape: MapShape::Square,
                 corridor_width: 2,
                 corridor_height: 2,
                 corridor_errantness: 0.75,
+                maze_algorithm: Box::new(RecursiveBacktracker),
                 margins: (1, 3), // (x, y)
                 prune_length: 4,
+                dead_end_removal: DeadEndRemoval::Short,
+                disconnected_policy: DisconnectedPolicy::Reconnect,
+                door_width: 1,
+                door_kinds: vec![],
+                extra_connection_chance: 0f32,
+                loop_min_distance: 16,
+                secret_passage_share: 0f32,
+                symmetry: Symmetry::None,
+                walls: false,
+                perimeter: 0,
             },
+            seed: None,
         };
     }
-    pub fn size(mut self, width: u16, height: u16) -> Self {
+    /**
+     * How the map is built, `Layout::RecursiveDivision` only uses the size,
+     * shape, corridor size, disconnected policy, symmetry and perimeter options
+     * and always places walls. `Layout::Cave` doesn't use the room and maze options,
+     * `Layout::DrunkardsWalk` digs the tunnels in place of the rooms and the maze
+     */
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.options.layout = layout;
+        return self;
+    }
+    pub fn size(mut self, width: u32, height: u32) -> Self {
         self.options.width = width;
         self.options.height = height;
         return self;
@@ -66,14 +351,105 @@ impl Generator {
         self.options.iterations = iterations;
         return self;
     }
-    pub fn room_size(mut self, min: (u16, u16), max: (u16, u16)) -> Self {
+    pub fn room_size(mut self, min: (u32, u32), max: (u32, u32)) -> Self {
         self.options.room_min = min;
         self.options.room_max = max;
         return self;
     }
+    /**
+     * How the room sizes are picked, `RoomSizeDistribution::Weighted` uses the
+     * sizes of its classes instead of the room size
+     */
+    pub fn room_size_distribution(mut self, distribution: RoomSizeDistribution) -> Self {
+        self.options.room_size_distribution = distribution;
+        return self;
+    }
+    /**
+     * Rooms longer than this many times their width are cut down, 1 or more.
+     * Every room size has to allow the ratio, so a minimum size of (8, 3)
+     * needs a maximum height of at least 4 with a ratio of 2
+     */
+    pub fn max_aspect_ratio(mut self, ratio: f32) -> Self {
+        self.options.max_aspect_ratio = Some(ratio);
+        return self;
+    }
+    pub fn room_shape(mut self, shape: RoomShape) -> Self {
+        self.options.room_shapes = vec![shape];
+        return self;
+    }
+    /**
+     * Every room picks one of the shapes at random
+     */
+    pub fn room_shapes(mut self, shapes: Vec<RoomShape>) -> Self {
+        self.options.room_shapes = shapes;
+        return self;
+    }
+    /**
+     * How the rooms are spread over the map, `RoomPlacement::Bsp` spreads them
+     * evenly and joins them itself so the maze algorithm isn't used.
+     * `RoomPlacement::Separation` packs the rooms densely and ignores `iterations`
+     */
+    pub fn room_placement(mut self, placement: RoomPlacement) -> Self {
+        self.options.room_placement = placement;
+        return self;
+    }
+    /**
+     * Random placement keeps placing rooms past the iterations until there are
+     * at least this many rooms. With symmetry it counts the rooms before mirroring.
+     * Generating fails with `GenerateError::RoomTargetsMissed` if they don't fit
+     */
+    pub fn min_rooms(mut self, min_rooms: u32) -> Self {
+        self.options.min_rooms = min_rooms;
+        return self;
+    }
+    pub fn max_rooms(mut self, max_rooms: u32) -> Self {
+        self.options.max_rooms = Some(max_rooms);
+        return self;
+    }
+    /**
+     * 0-1, the share of the map that has to be covered by rooms, this works
+     * like the minimum amount of rooms
+     */
+    pub fn room_coverage(mut self, coverage: f32) -> Self {
+        self.options.room_coverage = coverage;
+        return self;
+    }
+    /**
+     * Registers a hand made room that is placed between the generated rooms,
+     * see `Prefab::from_ascii`
+     */
+    pub fn prefab(mut self, prefab: Prefab) -> Self {
+        self.options.prefabs.push(prefab);
+        return self;
+    }
+    /**
+     * 0-1, chance that a room is one of the prefabs instead of a generated room
+     */
+    pub fn prefab_chance(mut self, chance: f32) -> Self {
+        self.options.prefab_chance = chance;
+        return self;
+    }
+    /**
+     * Stamps a room at the position before the other rooms are placed, it's
+     * always connected to the rest of the map, also with
+     * `DisconnectedPolicy::Remove`. Generating fails with
+     * `GenerateError::FixedRoomUnreachable` when no corridor fits between them.
+     * Only used by `Layout::Dungeon`, with symmetry the position is inside of
+     * the part that's mirrored
+     */
+    pub fn with_fixed_room(mut self, x: u32, y: u32, width: u32, height: u32) -> Self {
+        self.options.fixed_rooms.push((x, y, width, height));
+        return self;
+    }
+    /**
+     * Keeps the area solid rock so nothing is built there, with symmetry
+     * the area is inside of the part that's mirrored
+     */
+    pub fn reserve(mut self, area: ReservedArea) -> Self {
+        self.options.reserved.push(area);
+        return self;
+    }
     pub fn margins(mut self, horizontal: u8, vertical: u8) -> Self {
-        assert!(horizontal > 0, "Horizontal margin must be greater than 0");
-        assert!(vertical > 0, "Vertical margin must be greater than 0");
         self.options.margins = (horizontal, vertical);
         return self;
     }
@@ -82,58 +458,290 @@ impl Generator {
         self.options.corridor_height = height;
         return self;
     }
+    /**
+     * How likely a corridor keeps going in the same direction, only the
+     * `RecursiveBacktracker` uses it and the other maze algorithms ignore it
+     */
     pub fn corridor_errantness(mut self, errantness: f32) -> Self {
         self.options.corridor_errantness = errantness;
         return self;
     }
+    /**
+     * The algorithm that carves the corridors between the rooms, the default
+     * `RecursiveBacktracker` is the only one that uses the corridor errantness
+     */
+    pub fn maze_algorithm<A: MazeAlgorithm + 'static>(mut self, algorithm: A) -> Self {
+        self.options.maze_algorithm = Box::new(algorithm);
+        return self;
+    }
     pub fn prune_length(mut self, prune_length: u32) -> Self {
         self.options.prune_length = prune_length;
         return self;
     }
+    /**
+     * How dead end corridors are removed, `DeadEndRemoval::Short` uses the prune length
+     */
+    pub fn dead_end_removal(mut self, removal: DeadEndRemoval) -> Self {
+        self.options.dead_end_removal = removal;
+        return self;
+    }
+    /**
+     * Whether rooms and corridors that can't be reached are connected
+     * with a new corridor or removed
+     */
+    pub fn disconnected_policy(mut self, policy: DisconnectedPolicy) -> Self {
+        self.options.disconnected_policy = policy;
+        return self;
+    }
+    /**
+     * How many cells wide doors are, doors are never wider than the connection
+     */
+    pub fn door_width(mut self, width: u8) -> Self {
+        self.options.door_width = width;
+        return self;
+    }
+    /**
+     * The kinds of doors to place where connections meet rooms, each kind
+     * is picked relative to its weight
+     */
+    pub fn door_kinds(mut self, kinds: Vec<(DoorKind, u32)>) -> Self {
+        self.options.door_kinds = kinds;
+        return self;
+    }
+    /**
+     * Chance (0-1) that an unused connection between two sections is opened,
+     * which creates loops in the dungeon
+     */
+    pub fn extra_connection_chance(mut self, chance: f32) -> Self {
+        self.options.extra_connection_chance = chance;
+        return self;
+    }
+    /**
+     * The shortest walk between the two sides of a loop connection,
+     * stops short loops from being opened
+     */
+    pub fn loop_min_distance(mut self, distance: u32) -> Self {
+        self.options.loop_min_distance = distance;
+        return self;
+    }
+    /**
+     * Share (0-1) of the unused connections between sections that are turned
+     * into secret passages, they are stored in `Map::secret_connections`
+     */
+    pub fn secret_passage_share(mut self, share: f32) -> Self {
+        self.options.secret_passage_share = share;
+        return self;
+    }
+    /**
+     * Generates one part of the map and mirrors or rotates it onto the rest,
+     * generating fails with `GenerateError::SymmetryDisconnected` if the
+     * copies can't be bridged
+     */
+    pub fn symmetry(mut self, symmetry: Symmetry) -> Self {
+        self.options.symmetry = symmetry;
+        return self;
+    }
+    /**
+     * Turns the rock next to rooms, corridors and connections into walls
+     */
+    pub fn walls(mut self, walls: bool) -> Self {
+        self.options.walls = walls;
+        return self;
+    }
+    /**
+     * Marks the rock within `perimeter` cells of a wall with its distance
+     * to the wall, only used when walls are placed
+     */
+    pub fn perimeter(mut self, perimeter: u8) -> Self {
+        self.options.perimeter = perimeter;
+        return self;
+    }
+    /**
+     * Sets the seed used for every random decision in the generator,
+     * the same options and seed will always result in the same map
+     */
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        return self;
+    }
+    /**
+     * Generates a map from the set seed, or a random seed if none is set.
+     * The seed used is stored in `Map::seed`.
+     *
+     * Panics if the options are invalid, see `try_generate`
+     */
     pub fn generate(self) -> Map {
+        match self.try_generate() {
+            Ok(map) => return map,
+            Err(err) => panic!("{}", err),
+        }
+    }
+    /**
+     * Generates a map using the supplied rng, any seed set on the generator
+     * is ignored.
+     *
+     * Panics if the options are invalid, see `try_generate_with_rng`
+     */
+    pub fn generate_with_rng<R: Rng>(self, rng: &mut R) -> Map {
+        match self.try_generate_with_rng(rng) {
+            Ok(map) => return map,
+            Err(err) => panic!("{}", err),
+        }
+    }
+    /**
+     * Same as `generate` but returns an error instead of panicking
+     * when the options can't generate a map
+     */
+    pub fn try_generate(self) -> Result<Map, GenerateError> {
+        let seed = match self.seed {
+            Some(seed) => seed,
+            None => thread_rng().gen(),
+        };
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut map = self.try_generate_with_rng(&mut rng)?;
+        map.seed = Some(seed);
+        return Ok(map);
+    }
+    /**
+     * Same as `generate_with_rng` but returns an error instead of panicking
+     * when the options can't generate a map
+     */
+    pub fn try_generate_with_rng<R: Rng>(self, rng: &mut R) -> Result<Map, GenerateError> {
+        self.options.validate()?;
         let options = self.options;
-        let mut map = Map::new(options.width, options.height, Cell::SolidRock);
-
-        // Fill the map with some Rocks as SolidRocks are unbreakable
-        for y in 0..map.height {
-            for x in 0..map.width {
-                // if cell is inside oval
-                let r_x = map.width as f32 / 2f32;
-                let r_y = map.height as f32 / 2f32;
-                let r_x_2 = r_x.powf(2f32);
-                let r_y_2 = r_y.powf(2f32);
-                if ((x as f32 - r_x).powf(2f32) / r_x_2) + ((y as f32 - r_y).powf(2f32) / r_y_2)
-                    <= 1f32
-                {
-                    map.set(x, y, Cell::Rock)
+        let mut map = empty_map(&options);
+
+        let walls = match &options.layout {
+            Layout::Dungeon => {
+                // Rooms that miss the room count or coverage are placed again
+                let mut attempt = 1;
+                while let Err(err) = generate_rooms(rng, &mut map, &options) {
+                    if attempt == ROOM_ATTEMPTS {
+                        return Err(err);
+                    }
+                    attempt += 1;
+                    map = empty_map(&options);
                 }
+                match options.room_placement {
+                    RoomPlacement::Bsp(_) => {}
+                    _ => map = options.maze_algorithm.carve(rng, map, &options),
+                }
+                options.walls
+            }
+            Layout::RecursiveDivision(division) => {
+                divide(rng, &mut map, division, &options)?;
+                true
+            }
+            Layout::Cave(cave) => {
+                generate_caves(rng, &mut map, cave);
+                options.walls
+            }
+            Layout::DrunkardsWalk(walk) => {
+                map = walk.carve(rng, map, &options);
+                options.walls
             }
+        };
+        if map.section_vec.is_empty() {
+            return Err(GenerateError::NoSections);
+        }
+
+        match options.layout {
+            Layout::Dungeon | Layout::Cave(_) | Layout::DrunkardsWalk(_) => {
+                map = SectionMerger::new(rng, map, &options).generate()?;
+            }
+            _ => {}
         }
+        // Removing every dead end leaves nothing of a map without rooms
+        if !map.cell_vector.iter().any(|cell| cell.is_walkable()) {
+            return Err(GenerateError::NoSections);
+        }
+        let mut map = options.symmetry.mirror(map, &options)?;
+        if walls {
+            place_walls(&mut map, options.perimeter);
+        }
+        return Ok(map);
+    }
+}
 
-        generate_rooms(
-            &mut map,
-            options.room_min,
-            options.room_max,
-            options.margins,
-            options.iterations,
-            options.shape,
+/**
+ * The part of the map that's generated, with the shape of the map filled with
+ * Rocks as SolidRocks are unbreakable
+ */
+fn empty_map(options: &GeneratorOptions) -> Map {
+    let (width, height) = options.symmetry.domain_size(options.width, options.height);
+    let mut map = Map::new(width, height, Cell::SolidRock);
+    options
+        .symmetry
+        .apply_shape(&options.shape, &mut map, options.width, options.height);
+    for area in options.reserved.iter() {
+        area.apply(&mut map);
+    }
+    return map;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(generator: Generator) -> Option<GenerateError> {
+        return generator.seed(0).try_generate().err();
+    }
+
+    #[test]
+    fn validate_reports_invalid_options() {
+        assert_eq!(
+            error(Generator::new().size(0, 10)),
+            Some(GenerateError::EmptyMap)
+        );
+        assert_eq!(
+            error(Generator::new().room_size((0, 3), (5, 5))),
+            Some(GenerateError::InvalidRoomSize {
+                min: (0, 3),
+                max: (5, 5)
+            })
+        );
+        assert_eq!(
+            error(Generator::new().room_size((6, 6), (5, 5))),
+            Some(GenerateError::InvalidRoomSize {
+                min: (6, 6),
+                max: (5, 5)
+            })
         );
-        let map = LabyrinthGenerator::new(
-            map,
-            options.corridor_width,
-            options.corridor_height,
-            options.corridor_errantness,
-            options.margins,
-        )
-        .generate();
-
-        // place_walls(&mut cell_matrix);
-        return SectionMerger::new(
-            map,
-            options.margins,
-            (options.corridor_width, options.corridor_height),
-            options.prune_length,
-        )
-        .generate();
+        assert_eq!(
+            error(Generator::new().room_shapes(vec![])),
+            Some(GenerateError::NoRoomShapes)
+        );
+        assert_eq!(
+            error(Generator::new().room_size((4, 4), (128, 8))),
+            Some(GenerateError::RoomLargerThanMap {
+                room: (128, 8),
+                map: (64, 32)
+            })
+        );
+        assert_eq!(
+            error(Generator::new().corridor_size(0, 1)),
+            Some(GenerateError::InvalidCorridorSize)
+        );
+        assert_eq!(
+            error(
+                Generator::new()
+                    .size(3, 3)
+                    .room_size((1, 1), (1, 1))
+                    .corridor_size(3, 1)
+            ),
+            Some(GenerateError::CorridorLargerThanMap {
+                corridor: (3, 1),
+                map: (3, 3)
+            })
+        );
+        assert_eq!(
+            error(Generator::new().margins(0, 1)),
+            Some(GenerateError::InvalidMargins((0, 1)))
+        );
+    }
+
+    #[test]
+    fn valid_options_generate() {
+        assert!(Generator::new().seed(0).try_generate().is_ok());
     }
 }
@@ -0,0 +1,205 @@
+use crate::cell_matrix::{Cell, Map};
+
+use bmp::Image;
+
+/**
+ * The outline of the map, cells inside of the shape are carvable rock
+ * and cells outside of it are unbreakable solid rock
+ */
+pub enum MapShape {
+    // Fills the whole map
+    Square,
+    // An ellipse touching the edges of the map
+    Circle,
+    // Predicate called with (x, y, width, height), returns true if the cell
+    // is inside of the shape
+    Custom(Box<dyn Fn(u32, u32, u32, u32) -> bool>),
+    // A grid of cells that are inside of the shape, stretched to the size
+    // of the map, see `MapShape::from_bitmap` and `MapShape::from_ascii`
+    Mask {
+        width: u32,
+        height: u32,
+        cells: Vec<bool>,
+    },
+}
+
+impl MapShape {
+    pub fn custom<F>(func: F) -> MapShape
+    where
+        F: Fn(u32, u32, u32, u32) -> bool + 'static,
+    {
+        return MapShape::Custom(Box::new(func));
+    }
+    /**
+     * Creates a mask from an image where black pixels are outside of the
+     * shape and every other pixel is inside of it
+     */
+    pub fn from_bitmap(image: &Image) -> MapShape {
+        let width = image.get_width();
+        let height = image.get_height();
+        let mut cells = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = image.get_pixel(x, y);
+                // Anything dark enough counts as black
+                let brightness = (pixel.r as u32 + pixel.g as u32 + pixel.b as u32) / 3;
+                cells.push(brightness >= 128);
+            }
+        }
+        return MapShape::Mask {
+            width,
+            height,
+            cells,
+        };
+    }
+    /**
+     * Creates a mask from an ascii grid where `#` is outside of the shape
+     * and every other character is inside of it, lines shorter than the
+     * longest line are padded with cells inside of the shape
+     */
+    pub fn from_ascii(grid: &str) -> MapShape {
+        let lines: Vec<&str> = grid.lines().collect();
+        let width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0) as u32;
+        let height = lines.len() as u32;
+        let mut cells = Vec::with_capacity(width as usize * height as usize);
+        for line in lines {
+            let mut chars = line.chars();
+            for _ in 0..width {
+                cells.push(chars.next() != Some('#'));
+            }
+        }
+        return MapShape::Mask {
+            width,
+            height,
+            cells,
+        };
+    }
+    /**
+     * Masks need a cell for every position of their width and height
+     */
+    pub(crate) fn is_valid(&self) -> bool {
+        match self {
+            MapShape::Mask {
+                width,
+                height,
+                cells,
+            } => return cells.len() == *width as usize * *height as usize,
+            _ => return true,
+        }
+    }
+    /**
+     * Returns true if the position is inside of the shape on a map
+     * with the given width and height
+     */
+    pub fn contains(&self, x: u32, y: u32, width: u32, height: u32) -> bool {
+        match self {
+            MapShape::Square => return x < width && y < height,
+            MapShape::Circle => {
+                let r_x = width as f32 / 2f32;
+                let r_y = height as f32 / 2f32;
+                return ((x as f32 - r_x).powf(2f32) / r_x.powf(2f32))
+                    + ((y as f32 - r_y).powf(2f32) / r_y.powf(2f32))
+                    <= 1f32;
+            }
+            MapShape::Custom(func) => return func(x, y, width, height),
+            MapShape::Mask {
+                width: mask_width,
+                height: mask_height,
+                cells,
+            } => {
+                if *mask_width == 0 || *mask_height == 0 || x >= width || y >= height {
+                    return false;
+                }
+                let mask_x = x as u64 * *mask_width as u64 / width as u64;
+                let mask_y = y as u64 * *mask_height as u64 / height as u64;
+                return cells[(mask_y * *mask_width as u64 + mask_x) as usize];
+            }
+        }
+    }
+    /**
+     * Sets every cell inside of the shape to rock and every cell outside
+     * of it to solid rock
+     */
+    pub fn apply(&self, map: &mut Map) {
+        for y in 0..map.height {
+            for x in 0..map.width {
+                let cell = if self.contains(x, y, map.width, map.height) {
+                    Cell::Rock
+                } else {
+                    Cell::SolidRock
+                };
+                map.set(x, y, cell);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::GenerateError;
+    use crate::map_generator::Generator;
+
+    #[test]
+    fn custom_shape_keeps_the_outside_solid() {
+        for seed in 0..5 {
+            let map = Generator::new()
+                .seed(seed)
+                .shape(MapShape::custom(|x, _y, width, _height| x < width / 2))
+                .generate();
+            for (x, _y, cell) in map.iter() {
+                if x >= map.width / 2 {
+                    assert!(*cell == Cell::SolidRock);
+                }
+            }
+            assert!(map.iter().any(|(_, _, cell)| cell.is_walkable()));
+        }
+    }
+
+    #[test]
+    fn circle_contains_the_center_but_not_the_corners() {
+        let circle = MapShape::Circle;
+        assert!(circle.contains(32, 16, 64, 32));
+        assert!(!circle.contains(0, 0, 64, 32));
+        assert!(!circle.contains(63, 31, 64, 32));
+        let mut map = Map::new(64, 32, Cell::Rock);
+        circle.apply(&mut map);
+        assert!(*map.get(0, 0) == Cell::SolidRock);
+        assert!(*map.get(32, 16) == Cell::Rock);
+    }
+
+    #[test]
+    fn mask_without_a_cell_for_every_position() {
+        let shape = MapShape::Mask {
+            width: 10,
+            height: 10,
+            cells: vec![true; 5],
+        };
+        let map = Generator::new().shape(shape).try_generate();
+        assert_eq!(map.err(), Some(GenerateError::InvalidShapeMask));
+    }
+
+    #[test]
+    fn ascii_mask_is_stretched_to_the_map() {
+        let shape = MapShape::from_ascii("#.\n..");
+        assert!(!shape.contains(0, 0, 64, 32));
+        assert!(!shape.contains(31, 15, 64, 32));
+        assert!(shape.contains(32, 15, 64, 32));
+        assert!(shape.contains(31, 16, 64, 32));
+        assert!(!shape.contains(64, 0, 64, 32));
+    }
+
+    #[test]
+    fn bitmap_mask_is_outside_on_black() {
+        let mut image = Image::new(2, 1);
+        image.set_pixel(0, 0, bmp::Pixel::new(0, 0, 0));
+        image.set_pixel(1, 0, bmp::Pixel::new(255, 255, 255));
+        let shape = MapShape::from_bitmap(&image);
+        assert!(!shape.contains(0, 0, 10, 10));
+        assert!(shape.contains(9, 9, 10, 10));
+    }
+}
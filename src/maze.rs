@@ -0,0 +1,927 @@
+use crate::cell_matrix::{Cell, Map};
+use crate::corridor_tree::{CorridorNode, WrappedCorridorNode};
+use crate::direction::Direction;
+use crate::labyrinth_generator::{
+    is_suitable_corridor_location, next_corridor_location, LabyrinthGenerator,
+};
+use crate::map_generator::GeneratorOptions;
+
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore};
+
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+/**
+ * Carves the corridors into the rock between the rooms
+ */
+pub trait MazeAlgorithm {
+    /**
+     * Carves corridors into the map, every corridor gets its own index and
+     * its pieces are added to `Map::corridor_tree`
+     */
+    fn carve(&self, rng: &mut dyn RngCore, map: Map, options: &GeneratorOptions) -> Map;
+}
+
+/**
+ * Randomized depth first walk that follows the rock around the rooms,
+ * long winding corridors with few branches. The only algorithm that
+ * uses the corridor errantness
+ */
+pub struct RecursiveBacktracker;
+
+/**
+ * Randomized Prim's, grows the maze from a random frontier cell,
+ * lots of short dead ends
+ */
+pub struct Prim;
+
+/**
+ * Randomized Kruskal's, joins random walls between separate trees,
+ * lots of short dead ends and no direction bias
+ */
+pub struct Kruskal;
+
+/**
+ * Wilson's, loop erased random walks, an unbiased maze
+ */
+pub struct Wilson;
+
+/**
+ * Aldous-Broder, a random walk that carves into unvisited cells, an unbiased
+ * maze but slow on big maps
+ */
+pub struct AldousBroder;
+
+/**
+ * Growing tree, the selection decides which active cell is grown next
+ */
+pub struct GrowingTree(pub GrowingTreeSelection);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GrowingTreeSelection {
+    // Long winding corridors, the same as a backtracker
+    Newest,
+    // Straight corridors spreading out from the start
+    Oldest,
+    // Short dead ends, the same as Prim's
+    Random,
+    // Chance (0-1) to pick the newest cell, otherwise a random one
+    Mixed(f32),
+}
+
+/**
+ * Hunt and kill, random walks that restart next to the carved maze,
+ * long corridors with few dead ends
+ */
+pub struct HuntAndKill;
+
+/**
+ * Eller's, builds the maze one row at a time, horizontal runs
+ * with vertical branches
+ */
+pub struct Eller;
+
+/**
+ * Binary tree, every cell links north or east, diagonal texture with
+ * long corridors along the north and east sides
+ */
+pub struct BinaryTree;
+
+/**
+ * Sidewinder, horizontal runs that each link north once, long corridor
+ * along the north side
+ */
+pub struct Sidewinder;
+
+impl MazeAlgorithm for RecursiveBacktracker {
+    fn carve(&self, rng: &mut dyn RngCore, map: Map, options: &GeneratorOptions) -> Map {
+        return LabyrinthGenerator::new(
+            rng,
+            map,
+            options.corridor_width,
+            options.corridor_height,
+            options.corridor_errantness,
+            options.margins,
+        )
+        .generate();
+    }
+}
+
+impl MazeAlgorithm for Prim {
+    fn carve(&self, rng: &mut dyn RngCore, map: Map, options: &GeneratorOptions) -> Map {
+        return carve_grid(rng, map, options, |grid, rng| self.link(grid, rng));
+    }
+}
+
+impl Prim {
+    fn link(&self, grid: &mut MazeGrid, rng: &mut dyn RngCore) {
+        let mut in_maze = vec![false; grid.len()];
+        let mut in_frontier = vec![false; grid.len()];
+        for component in grid.components() {
+            let start = *component.choose(rng).unwrap();
+            in_maze[start] = true;
+            let mut frontier = vec![];
+            for neighbor in grid.neighbors(start) {
+                in_frontier[neighbor] = true;
+                frontier.push(neighbor);
+            }
+            while !frontier.is_empty() {
+                let cell = frontier.swap_remove(rng.gen_range(0, frontier.len()));
+                let neighbor = grid.random_neighbor(rng, cell, |n| in_maze[n]).unwrap();
+                grid.link(cell, neighbor);
+                in_maze[cell] = true;
+                for next in grid.neighbors(cell) {
+                    if !in_maze[next] && !in_frontier[next] {
+                        in_frontier[next] = true;
+                        frontier.push(next);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl MazeAlgorithm for Kruskal {
+    fn carve(&self, rng: &mut dyn RngCore, map: Map, options: &GeneratorOptions) -> Map {
+        return carve_grid(rng, map, options, |grid, rng| self.link(grid, rng));
+    }
+}
+
+impl Kruskal {
+    fn link(&self, grid: &mut MazeGrid, rng: &mut dyn RngCore) {
+        let mut edges = vec![];
+        for cell in 0..grid.len() {
+            if let Some(east) = grid.neighbor(cell, Direction::E) {
+                edges.push((cell, east));
+            }
+            if let Some(south) = grid.neighbor(cell, Direction::S) {
+                edges.push((cell, south));
+            }
+        }
+        edges.shuffle(rng);
+        let mut sets = DisjointSets::new(grid.len());
+        for (a, b) in edges {
+            if sets.union(a, b) {
+                grid.link(a, b);
+            }
+        }
+    }
+}
+
+impl MazeAlgorithm for Wilson {
+    fn carve(&self, rng: &mut dyn RngCore, map: Map, options: &GeneratorOptions) -> Map {
+        return carve_grid(rng, map, options, |grid, rng| self.link(grid, rng));
+    }
+}
+
+impl Wilson {
+    fn link(&self, grid: &mut MazeGrid, rng: &mut dyn RngCore) {
+        let mut in_maze = vec![false; grid.len()];
+        // Where the walk last left every cell, overwriting it erases loops
+        let mut next = vec![0; grid.len()];
+        for mut component in grid.components() {
+            component.shuffle(rng);
+            in_maze[component[0]] = true;
+            for start in component {
+                let mut cell = start;
+                while !in_maze[cell] {
+                    next[cell] = grid.random_neighbor(rng, cell, |_| true).unwrap();
+                    cell = next[cell];
+                }
+                let mut cell = start;
+                while !in_maze[cell] {
+                    in_maze[cell] = true;
+                    grid.link(cell, next[cell]);
+                    cell = next[cell];
+                }
+            }
+        }
+    }
+}
+
+impl MazeAlgorithm for AldousBroder {
+    fn carve(&self, rng: &mut dyn RngCore, map: Map, options: &GeneratorOptions) -> Map {
+        return carve_grid(rng, map, options, |grid, rng| self.link(grid, rng));
+    }
+}
+
+impl AldousBroder {
+    fn link(&self, grid: &mut MazeGrid, rng: &mut dyn RngCore) {
+        let mut in_maze = vec![false; grid.len()];
+        for component in grid.components() {
+            let mut cell = *component.choose(rng).unwrap();
+            in_maze[cell] = true;
+            let mut remaining = component.len() - 1;
+            while remaining > 0 {
+                let neighbor = grid.random_neighbor(rng, cell, |_| true).unwrap();
+                if !in_maze[neighbor] {
+                    in_maze[neighbor] = true;
+                    grid.link(cell, neighbor);
+                    remaining -= 1;
+                }
+                cell = neighbor;
+            }
+        }
+    }
+}
+
+impl MazeAlgorithm for GrowingTree {
+    fn carve(&self, rng: &mut dyn RngCore, map: Map, options: &GeneratorOptions) -> Map {
+        return carve_grid(rng, map, options, |grid, rng| self.link(grid, rng));
+    }
+}
+
+impl GrowingTree {
+    fn link(&self, grid: &mut MazeGrid, rng: &mut dyn RngCore) {
+        let selection = self.0;
+        let mut in_maze = vec![false; grid.len()];
+        for component in grid.components() {
+            let start = *component.choose(rng).unwrap();
+            in_maze[start] = true;
+            let mut active = vec![start];
+            while !active.is_empty() {
+                let idx = match selection {
+                    GrowingTreeSelection::Newest => active.len() - 1,
+                    GrowingTreeSelection::Oldest => 0,
+                    GrowingTreeSelection::Random => rng.gen_range(0, active.len()),
+                    GrowingTreeSelection::Mixed(newest_chance) => {
+                        if rng.gen::<f32>() < newest_chance {
+                            active.len() - 1
+                        } else {
+                            rng.gen_range(0, active.len())
+                        }
+                    }
+                };
+                let cell = active[idx];
+                match grid.random_neighbor(rng, cell, |n| !in_maze[n]) {
+                    Some(neighbor) => {
+                        in_maze[neighbor] = true;
+                        grid.link(cell, neighbor);
+                        active.push(neighbor);
+                    }
+                    None => {
+                        active.remove(idx);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl MazeAlgorithm for HuntAndKill {
+    fn carve(&self, rng: &mut dyn RngCore, map: Map, options: &GeneratorOptions) -> Map {
+        return carve_grid(rng, map, options, |grid, rng| self.link(grid, rng));
+    }
+}
+
+impl HuntAndKill {
+    fn link(&self, grid: &mut MazeGrid, rng: &mut dyn RngCore) {
+        let mut in_maze = vec![false; grid.len()];
+        for component in grid.components() {
+            let mut cell = *component.choose(rng).unwrap();
+            in_maze[cell] = true;
+            // Everything before the first unvisited cell is part of the maze
+            let mut first_unvisited = 0;
+            loop {
+                // Kill, walk until there's nowhere left to go
+                if let Some(neighbor) = grid.random_neighbor(rng, cell, |n| !in_maze[n]) {
+                    in_maze[neighbor] = true;
+                    grid.link(cell, neighbor);
+                    cell = neighbor;
+                    continue;
+                }
+                // Hunt, find an unvisited cell next to the maze
+                while first_unvisited < component.len() && in_maze[component[first_unvisited]] {
+                    first_unvisited += 1;
+                }
+                let hunted = component[first_unvisited..].iter().find_map(|c| {
+                    if in_maze[*c] {
+                        return None;
+                    }
+                    return grid.neighbors(*c).find(|n| in_maze[*n]).map(|_| *c);
+                });
+                match hunted {
+                    Some(hunted) => {
+                        let neighbor = grid.random_neighbor(rng, hunted, |n| in_maze[n]);
+                        in_maze[hunted] = true;
+                        grid.link(hunted, neighbor.unwrap());
+                        cell = hunted;
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+}
+
+impl MazeAlgorithm for Eller {
+    fn carve(&self, rng: &mut dyn RngCore, map: Map, options: &GeneratorOptions) -> Map {
+        return carve_grid(rng, map, options, |grid, rng| self.link(grid, rng));
+    }
+}
+
+impl Eller {
+    fn link(&self, grid: &mut MazeGrid, rng: &mut dyn RngCore) {
+        let mut sets = DisjointSets::new(grid.len());
+        for row in 0..grid.rows {
+            let last_row = row == grid.rows - 1;
+            let cells: Vec<usize> = (row * grid.columns..(row + 1) * grid.columns)
+                .filter(|c| grid.open[*c])
+                .collect();
+            // Randomly join neighbouring sets, the last row joins all of them
+            for cell in &cells {
+                if let Some(east) = grid.neighbor(*cell, Direction::E) {
+                    if sets.find(*cell) != sets.find(east) && (last_row || rng.gen::<bool>()) {
+                        sets.union(*cell, east);
+                        grid.link(*cell, east);
+                    }
+                }
+            }
+            if last_row {
+                break;
+            }
+            // Every set continues down at least once, if it can
+            let mut groups: Vec<Vec<usize>> = vec![];
+            let mut group_of_set: HashMap<usize, usize> = HashMap::new();
+            for cell in &cells {
+                if grid.neighbor(*cell, Direction::S).is_none() {
+                    continue;
+                }
+                let set = sets.find(*cell);
+                let idx = *group_of_set.entry(set).or_insert_with(|| {
+                    groups.push(vec![]);
+                    return groups.len() - 1;
+                });
+                groups[idx].push(*cell);
+            }
+            for mut group in groups {
+                group.shuffle(rng);
+                let count = rng.gen_range(1, group.len() + 1);
+                for cell in &group[..count] {
+                    let south = grid.neighbor(*cell, Direction::S).unwrap();
+                    sets.union(*cell, south);
+                    grid.link(*cell, south);
+                }
+            }
+        }
+    }
+}
+
+impl MazeAlgorithm for BinaryTree {
+    fn carve(&self, rng: &mut dyn RngCore, map: Map, options: &GeneratorOptions) -> Map {
+        return carve_grid(rng, map, options, |grid, rng| self.link(grid, rng));
+    }
+}
+
+impl BinaryTree {
+    fn link(&self, grid: &mut MazeGrid, rng: &mut dyn RngCore) {
+        for cell in 0..grid.len() {
+            let choices: Vec<usize> = [Direction::N, Direction::E]
+                .iter()
+                .filter_map(|d| grid.neighbor(cell, *d))
+                .collect();
+            if let Some(neighbor) = choices.choose(rng) {
+                grid.link(cell, *neighbor);
+            }
+        }
+    }
+}
+
+impl MazeAlgorithm for Sidewinder {
+    fn carve(&self, rng: &mut dyn RngCore, map: Map, options: &GeneratorOptions) -> Map {
+        return carve_grid(rng, map, options, |grid, rng| self.link(grid, rng));
+    }
+}
+
+impl Sidewinder {
+    fn link(&self, grid: &mut MazeGrid, rng: &mut dyn RngCore) {
+        for cell in 0..grid.len() {
+            if !grid.open[cell] {
+                continue;
+            }
+            let east = grid.neighbor(cell, Direction::E);
+            // The run is every cell linked to the west of this one
+            let mut run = vec![cell];
+            while let Some(west) = grid.neighbor(run[run.len() - 1], Direction::W) {
+                if !grid.is_linked(west, run[run.len() - 1]) {
+                    break;
+                }
+                run.push(west);
+            }
+            let north: Vec<usize> = run
+                .iter()
+                .filter(|c| grid.neighbor(**c, Direction::N).is_some())
+                .cloned()
+                .collect();
+            let close_run = match east {
+                Some(_) => !north.is_empty() && rng.gen::<bool>(),
+                None => true,
+            };
+            if !close_run {
+                grid.link(cell, east.unwrap());
+            } else if let Some(from) = north.choose(rng) {
+                let to = grid.neighbor(*from, Direction::N).unwrap();
+                grid.link(*from, to);
+            }
+        }
+    }
+}
+
+/**
+ * Lays a grid of corridor sized cells over the rock, lets the algorithm
+ * link the cells and carves the links as corridors
+ */
+fn carve_grid<F>(rng: &mut dyn RngCore, map: Map, options: &GeneratorOptions, link: F) -> Map
+where
+    F: FnOnce(&mut MazeGrid, &mut dyn RngCore),
+{
+    let mut grid = MazeGrid::new(rng, &map, options);
+    link(&mut grid, rng);
+    return grid.carve(map, options);
+}
+
+// Corridors are a corridor apart with margins between them, so unlinked
+// cells are never close enough to join
+struct MazeGrid {
+    columns: usize,
+    rows: usize,
+    // Position of the first cell on the map
+    origin: (u32, u32),
+    // Distance between the cells on the map
+    step: (u32, u32),
+    // Whether a corridor fits in the cell
+    open: Vec<bool>,
+    // Whether the cells between the cell and its east and south neighbours
+    // are rock, solid rock from the map shape or a reserved area keeps them apart
+    east_gap: Vec<bool>,
+    south_gap: Vec<bool>,
+    // Whether the cell is linked to its east and south neighbours
+    east: Vec<bool>,
+    south: Vec<bool>,
+}
+
+impl MazeGrid {
+    fn new(rng: &mut dyn RngCore, map: &Map, options: &GeneratorOptions) -> MazeGrid {
+        let (corridor_width, corridor_height) = (
+            options.corridor_width as u32,
+            options.corridor_height as u32,
+        );
+        let step = (
+            corridor_width + options.margins.0 as u32,
+            corridor_height + options.margins.1 as u32,
+        );
+        let origin = (rng.gen_range(0, step.0), rng.gen_range(0, step.1));
+        let count = |size: u32, origin: u32, step: u32, corridor: u32| {
+            if size < origin + corridor {
+                return 0;
+            }
+            return ((size - origin - corridor) / step + 1) as usize;
+        };
+        let columns = count(map.width, origin.0, step.0, corridor_width);
+        let rows = count(map.height, origin.1, step.1, corridor_height);
+        let mut open = vec![false; columns * rows];
+        for (cell, is_open) in open.iter_mut().enumerate() {
+            let x = origin.0 + (cell % columns) as u32 * step.0;
+            let y = origin.1 + (cell / columns) as u32 * step.1;
+            *is_open = is_suitable_corridor_location(
+                map,
+                x as i32,
+                y as i32,
+                options.corridor_width,
+                options.corridor_height,
+                (
+                    options.margins.0,
+                    options.margins.1,
+                    options.margins.0,
+                    options.margins.1,
+                ),
+            );
+        }
+        let is_rock = |x: u32, y: u32, width: u32, height: u32| {
+            return map
+                .rect_is(x as i32, y as i32, width, height, |c| !c.is_rock())
+                .is_none();
+        };
+        let mut east_gap = vec![false; open.len()];
+        let mut south_gap = vec![false; open.len()];
+        for cell in 0..open.len() {
+            let x = origin.0 + (cell % columns) as u32 * step.0;
+            let y = origin.1 + (cell / columns) as u32 * step.1;
+            east_gap[cell] = is_rock(
+                x + corridor_width,
+                y,
+                options.margins.0 as u32,
+                corridor_height,
+            );
+            south_gap[cell] = is_rock(
+                x,
+                y + corridor_height,
+                corridor_width,
+                options.margins.1 as u32,
+            );
+        }
+        return MazeGrid {
+            columns,
+            rows,
+            origin,
+            step,
+            east_gap,
+            south_gap,
+            east: vec![false; open.len()],
+            south: vec![false; open.len()],
+            open,
+        };
+    }
+
+    fn len(&self) -> usize {
+        return self.open.len();
+    }
+
+    fn position(&self, cell: usize) -> (u32, u32) {
+        return (
+            self.origin.0 + (cell % self.columns) as u32 * self.step.0,
+            self.origin.1 + (cell / self.columns) as u32 * self.step.1,
+        );
+    }
+
+    /**
+     * The open cell next to the cell in the direction, if there's
+     * rock between them
+     */
+    fn neighbor(&self, cell: usize, direction: Direction) -> Option<usize> {
+        if !self.open[cell] {
+            return None;
+        }
+        let (column, row) = (cell % self.columns, cell / self.columns);
+        let neighbor = match direction {
+            Direction::N if row > 0 && self.south_gap[cell - self.columns] => cell - self.columns,
+            Direction::E if column + 1 < self.columns && self.east_gap[cell] => cell + 1,
+            Direction::S if row + 1 < self.rows && self.south_gap[cell] => cell + self.columns,
+            Direction::W if column > 0 && self.east_gap[cell - 1] => cell - 1,
+            _ => return None,
+        };
+        if !self.open[neighbor] {
+            return None;
+        }
+        return Some(neighbor);
+    }
+
+    fn neighbors(&self, cell: usize) -> impl Iterator<Item = usize> + '_ {
+        return [Direction::N, Direction::E, Direction::S, Direction::W]
+            .iter()
+            .filter_map(move |d| self.neighbor(cell, *d));
+    }
+
+    fn random_neighbor<F>(&self, rng: &mut dyn RngCore, cell: usize, filter: F) -> Option<usize>
+    where
+        F: Fn(usize) -> bool,
+    {
+        let mut choices = [0; 4];
+        let mut count = 0;
+        for neighbor in self.neighbors(cell).filter(|n| filter(*n)) {
+            choices[count] = neighbor;
+            count += 1;
+        }
+        return choices[..count].choose(rng).cloned();
+    }
+
+    fn link(&mut self, a: usize, b: usize) {
+        let (first, second) = (a.min(b), a.max(b));
+        if second == first + 1 {
+            self.east[first] = true;
+        } else {
+            self.south[first] = true;
+        }
+    }
+
+    fn is_linked(&self, a: usize, b: usize) -> bool {
+        let (first, second) = (a.min(b), a.max(b));
+        if second == first + 1 {
+            return self.east[first];
+        }
+        return self.south[first];
+    }
+
+    /**
+     * The open cells that can reach each other, sorted by index
+     */
+    fn components(&self) -> Vec<Vec<usize>> {
+        let mut seen = vec![false; self.len()];
+        let mut components = vec![];
+        for start in 0..self.len() {
+            if seen[start] || !self.open[start] {
+                continue;
+            }
+            seen[start] = true;
+            let mut component = vec![];
+            let mut stack = vec![start];
+            while let Some(cell) = stack.pop() {
+                component.push(cell);
+                for neighbor in self.neighbors(cell) {
+                    if !seen[neighbor] {
+                        seen[neighbor] = true;
+                        stack.push(neighbor);
+                    }
+                }
+            }
+            component.sort_unstable();
+            components.push(component);
+        }
+        return components;
+    }
+
+    /**
+     * Carves every tree of linked cells as a corridor, the corridor pieces
+     * are one cell apart like the ones from the backtracker
+     */
+    fn carve(self, mut map: Map, options: &GeneratorOptions) -> Map {
+        let corridor_size = (options.corridor_width, options.corridor_height);
+        let mut carved = vec![false; self.len()];
+        let mut nodes = vec![];
+        for start in 0..self.len() {
+            if carved[start] || !self.open[start] {
+                continue;
+            }
+            let corridor_index = map.add_corridor();
+            let (x, y) = self.position(start);
+            let root = add_piece(&mut map, corridor_index, corridor_size, x, y, None);
+            map.corridor_tree.push(Rc::clone(&root));
+            carved[start] = true;
+            let mut queue = VecDeque::new();
+            queue.push_back((start, root));
+            while let Some((cell, node)) = queue.pop_front() {
+                for direction in &[Direction::N, Direction::E, Direction::S, Direction::W] {
+                    let neighbor = match self.neighbor(cell, *direction) {
+                        Some(neighbor) if self.is_linked(cell, neighbor) => neighbor,
+                        _ => continue,
+                    };
+                    if carved[neighbor] {
+                        continue;
+                    }
+                    carved[neighbor] = true;
+                    let (to_x, to_y) = self.position(neighbor);
+                    let mut last = Rc::clone(&node);
+                    let (mut x, mut y) = self.position(cell);
+                    while (x, y) != (to_x, to_y) {
+                        x = step_towards(x, to_x);
+                        y = step_towards(y, to_y);
+                        last =
+                            add_piece(&mut map, corridor_index, corridor_size, x, y, Some(&last));
+                    }
+                    queue.push_back((neighbor, last));
+                }
+                nodes.push((cell, node, corridor_index));
+            }
+        }
+        // The grid leaves gaps next to the rooms that are too wide to connect,
+        // corridors continue towards closed cells until they reach the margins
+        for (cell, node, corridor_index) in nodes {
+            for direction in &[Direction::N, Direction::E, Direction::S, Direction::W] {
+                if self.has_open_cell(cell, *direction) {
+                    continue;
+                }
+                let mut last = Rc::clone(&node);
+                let (mut x, mut y) = (node.borrow().x, node.borrow().y);
+                while let Some((next_x, next_y)) =
+                    next_corridor_location(&map, x, y, *direction, corridor_size, options.margins)
+                {
+                    x = next_x;
+                    y = next_y;
+                    last = add_piece(&mut map, corridor_index, corridor_size, x, y, Some(&last));
+                }
+            }
+        }
+        return map;
+    }
+
+    /**
+     * Whether there's an open cell next to the cell in the direction
+     */
+    fn has_open_cell(&self, cell: usize, direction: Direction) -> bool {
+        let (column, row) = (cell % self.columns, cell / self.columns);
+        let neighbor = match direction {
+            Direction::N if row > 0 => cell - self.columns,
+            Direction::E if column + 1 < self.columns => cell + 1,
+            Direction::S if row + 1 < self.rows => cell + self.columns,
+            Direction::W if column > 0 => cell - 1,
+            _ => return false,
+        };
+        return self.open[neighbor];
+    }
+}
+
+fn step_towards(from: u32, to: u32) -> u32 {
+    if from < to {
+        return from + 1;
+    } else if from > to {
+        return from - 1;
+    }
+    return from;
+}
+
+fn add_piece(
+    map: &mut Map,
+    corridor_index: usize,
+    corridor_size: (u8, u8),
+    x: u32,
+    y: u32,
+    parent: Option<&WrappedCorridorNode>,
+) -> WrappedCorridorNode {
+    map.set_rect(
+        Cell::Corridor(corridor_index),
+        x,
+        y,
+        corridor_size.0 as u32,
+        corridor_size.1 as u32,
+    );
+    return CorridorNode::new(parent, x, y);
+}
+
+struct DisjointSets {
+    parents: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(size: usize) -> DisjointSets {
+        return DisjointSets {
+            parents: (0..size).collect(),
+        };
+    }
+
+    fn find(&mut self, item: usize) -> usize {
+        let mut root = item;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        // Point everything on the way straight to the root
+        let mut item = item;
+        while self.parents[item] != root {
+            let next = self.parents[item];
+            self.parents[item] = root;
+            item = next;
+        }
+        return root;
+    }
+
+    /**
+     * Joins the sets of a and b, false if they already were the same set
+     */
+    fn union(&mut self, a: usize, b: usize) -> bool {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
+        }
+        self.parents[b] = a;
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_generator::Generator;
+    use crate::map_shape::MapShape;
+    use crate::reserved::ReservedArea;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /**
+     * Links a seeded grid over an empty map and checks that every open
+     * cell is reached exactly once, a perfect maze
+     */
+    fn assert_perfect_maze<F>(link: F)
+    where
+        F: Fn(&mut MazeGrid, &mut dyn RngCore),
+    {
+        let options = Generator::new().size(40, 30).options;
+        let map = Map::new(options.width, options.height, Cell::Rock);
+        for seed in 0..8 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut grid = MazeGrid::new(&mut rng, &map, &options);
+            assert_eq!(grid.components().len(), 1);
+            link(&mut grid, &mut rng);
+
+            let cells = grid.open.iter().filter(|open| **open).count();
+            let edges = grid
+                .east
+                .iter()
+                .chain(grid.south.iter())
+                .filter(|l| **l)
+                .count();
+            assert!(cells > 1);
+            assert_eq!(edges, cells - 1);
+
+            let start = grid.open.iter().position(|open| *open).unwrap();
+            let mut seen = vec![false; grid.len()];
+            seen[start] = true;
+            let mut stack = vec![start];
+            while let Some(cell) = stack.pop() {
+                for neighbor in grid.neighbors(cell) {
+                    if !seen[neighbor] && grid.is_linked(cell, neighbor) {
+                        seen[neighbor] = true;
+                        stack.push(neighbor);
+                    }
+                }
+            }
+            assert_eq!(seen.iter().filter(|seen| **seen).count(), cells);
+        }
+    }
+
+    #[test]
+    fn prim_is_perfect() {
+        assert_perfect_maze(|grid, rng| Prim.link(grid, rng));
+    }
+
+    #[test]
+    fn kruskal_is_perfect() {
+        assert_perfect_maze(|grid, rng| Kruskal.link(grid, rng));
+    }
+
+    #[test]
+    fn wilson_is_perfect() {
+        assert_perfect_maze(|grid, rng| Wilson.link(grid, rng));
+    }
+
+    #[test]
+    fn aldous_broder_is_perfect() {
+        assert_perfect_maze(|grid, rng| AldousBroder.link(grid, rng));
+    }
+
+    #[test]
+    fn growing_tree_is_perfect() {
+        for selection in &[
+            GrowingTreeSelection::Newest,
+            GrowingTreeSelection::Oldest,
+            GrowingTreeSelection::Random,
+            GrowingTreeSelection::Mixed(0.5),
+        ] {
+            assert_perfect_maze(|grid, rng| GrowingTree(*selection).link(grid, rng));
+        }
+    }
+
+    #[test]
+    fn hunt_and_kill_is_perfect() {
+        assert_perfect_maze(|grid, rng| HuntAndKill.link(grid, rng));
+    }
+
+    #[test]
+    fn eller_is_perfect() {
+        assert_perfect_maze(|grid, rng| Eller.link(grid, rng));
+    }
+
+    #[test]
+    fn binary_tree_is_perfect() {
+        assert_perfect_maze(|grid, rng| BinaryTree.link(grid, rng));
+    }
+
+    #[test]
+    fn sidewinder_is_perfect() {
+        assert_perfect_maze(|grid, rng| Sidewinder.link(grid, rng));
+    }
+
+    /**
+     * Generates maps with a reserved area and maps with a round shape and
+     * checks that nothing was carved into the solid rock of either
+     */
+    fn assert_solid_rock_kept<A: MazeAlgorithm + 'static, F: Fn() -> A>(algorithm: F) {
+        for seed in 0..30 {
+            let reserved = Generator::new()
+                .seed(seed)
+                .size(17, 40)
+                .reserve(ReservedArea::Rect(1, 4, 5, 3))
+                .maze_algorithm(algorithm())
+                .generate();
+            for (x, y, cell) in reserved.iter() {
+                if (1..6).contains(&x) && (4..7).contains(&y) {
+                    assert!(*cell == Cell::SolidRock, "({}, {}) seed {}", x, y, seed);
+                }
+            }
+            let round = Generator::new()
+                .seed(seed)
+                .size(40, 40)
+                .shape(MapShape::Circle)
+                .maze_algorithm(algorithm())
+                .generate();
+            for (x, y, cell) in round.iter() {
+                if !MapShape::Circle.contains(x, y, round.width, round.height) {
+                    assert!(*cell == Cell::SolidRock, "({}, {}) seed {}", x, y, seed);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn grid_mazes_keep_solid_rock() {
+        assert_solid_rock_kept(|| Prim);
+        assert_solid_rock_kept(|| Kruskal);
+        assert_solid_rock_kept(|| Wilson);
+        assert_solid_rock_kept(|| AldousBroder);
+        assert_solid_rock_kept(|| GrowingTree(GrowingTreeSelection::Newest));
+        assert_solid_rock_kept(|| HuntAndKill);
+        assert_solid_rock_kept(|| Eller);
+        assert_solid_rock_kept(|| BinaryTree);
+        assert_solid_rock_kept(|| Sidewinder);
+    }
+}
@@ -0,0 +1,321 @@
+use crate::cell_matrix::{Cell, Map};
+use crate::drunkards_walk::is_diggable;
+use crate::error::PrefabParseError;
+use crate::map_generator::GeneratorOptions;
+use crate::room::Room;
+
+use rand::Rng;
+
+/**
+ * A hand made room read from an ASCII template. `.` is floor, `D` is floor
+ * that doors can be opened from and `#` or whitespace stays rock. Sockets have
+ * to be on the edge of the room, rooms stamped from a template with sockets
+ * are only connected through them
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prefab {
+    width: u32,
+    height: u32,
+    // Row by row, which cells are part of the room
+    footprint: Vec<bool>,
+    // Row by row, which cells are door sockets
+    sockets: Vec<bool>,
+    // How often the prefab is picked compared to the other prefabs
+    weight: u32,
+    // Whether the prefab can be turned by quarter turns
+    rotate: bool,
+    // Whether the prefab can be flipped
+    mirror: bool,
+}
+
+impl Prefab {
+    /**
+     * Reads a template, the rock around the floor is cut off so templates
+     * can be indented. Returns an error if the template has no floor, has
+     * other characters or a socket isn't next to rock
+     */
+    pub fn from_ascii(template: &str) -> Result<Prefab, PrefabParseError> {
+        let rows: Vec<Vec<char>> = template
+            .lines()
+            .map(|line| line.chars().collect())
+            .collect();
+        let is_floor = |x: i64, y: i64| {
+            if x < 0 || y < 0 {
+                return false;
+            }
+            let c = rows.get(y as usize).and_then(|row| row.get(x as usize));
+            return matches!(c, Some('.') | Some('D'));
+        };
+        // (x, y, socket) of every floor cell
+        let mut floor = vec![];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.iter().enumerate() {
+                let (x, y) = (x as u32, y as u32);
+                match c {
+                    '.' => floor.push((x, y, false)),
+                    'D' => {
+                        let (n_x, n_y) = (x as i64, y as i64);
+                        if is_floor(n_x, n_y - 1)
+                            && is_floor(n_x + 1, n_y)
+                            && is_floor(n_x, n_y + 1)
+                            && is_floor(n_x - 1, n_y)
+                        {
+                            return Err(PrefabParseError::SocketNotOnEdge { x, y });
+                        }
+                        floor.push((x, y, true));
+                    }
+                    c if *c == '#' || c.is_whitespace() => {}
+                    glyph => {
+                        return Err(PrefabParseError::UnknownGlyph {
+                            x,
+                            y,
+                            glyph: *glyph,
+                        })
+                    }
+                }
+            }
+        }
+        if floor.is_empty() {
+            return Err(PrefabParseError::Empty);
+        }
+        let left = floor.iter().map(|(x, _, _)| *x).min().unwrap();
+        let top = floor.iter().map(|(_, y, _)| *y).min().unwrap();
+        let width = floor.iter().map(|(x, _, _)| *x).max().unwrap() - left + 1;
+        let height = floor.iter().map(|(_, y, _)| *y).max().unwrap() - top + 1;
+        let mut footprint = vec![false; (width * height) as usize];
+        let mut sockets = vec![false; (width * height) as usize];
+        for (x, y, socket) in floor {
+            let idx = ((y - top) * width + x - left) as usize;
+            footprint[idx] = true;
+            sockets[idx] = socket;
+        }
+        return Ok(Prefab {
+            width,
+            height,
+            footprint,
+            sockets,
+            weight: 1,
+            rotate: false,
+            mirror: false,
+        });
+    }
+    /**
+     * How often the prefab is picked compared to the other prefabs, 1 by default
+     */
+    pub fn weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        return self;
+    }
+    /**
+     * Lets the prefab be placed turned by a quarter, half or three quarter turn
+     */
+    pub fn rotate(mut self, rotate: bool) -> Self {
+        self.rotate = rotate;
+        return self;
+    }
+    /**
+     * Lets the prefab be placed flipped from left to right
+     */
+    pub fn mirror(mut self, mirror: bool) -> Self {
+        self.mirror = mirror;
+        return self;
+    }
+    /**
+     * (width, height) of the template without the rock around it
+     */
+    pub fn size(&self) -> (u32, u32) {
+        return (self.width, self.height);
+    }
+}
+
+/**
+ * Rolls the prefab chance and picks a prefab by weight, turned and flipped
+ * as it allows. The room is at the top left of the map. Doesn't use the rng
+ * when there are no prefabs
+ */
+pub(crate) fn roll_prefab<R: Rng>(rng: &mut R, options: &GeneratorOptions) -> Option<Room> {
+    let prefabs = &options.prefabs;
+    let total_weight: u32 = prefabs.iter().map(|p| p.weight).sum();
+    if total_weight == 0 || rng.gen::<f32>() >= options.prefab_chance {
+        return None;
+    }
+    let mut roll = rng.gen_range(0, total_weight);
+    let mut idx = prefabs.len() - 1;
+    for (prefab_idx, prefab) in prefabs.iter().enumerate() {
+        if roll < prefab.weight {
+            idx = prefab_idx;
+            break;
+        }
+        roll -= prefab.weight;
+    }
+    let prefab = &prefabs[idx];
+    let flip = prefab.mirror && rng.gen::<bool>();
+    let turns = if prefab.rotate {
+        rng.gen_range(0, 4)
+    } else {
+        0
+    };
+    let (width, height, footprint) = transform(&prefab.footprint, prefab.size(), flip, turns);
+    let (_, _, sockets) = transform(&prefab.sockets, prefab.size(), flip, turns);
+    return Some(Room::from_prefab(width, height, footprint, sockets, idx));
+}
+
+/**
+ * Flips the cells from left to right and then turns them clockwise
+ */
+fn transform(cells: &[bool], size: (u32, u32), flip: bool, turns: u32) -> (u32, u32, Vec<bool>) {
+    let (mut width, mut height) = size;
+    let mut cells = cells.to_vec();
+    if flip {
+        for row in cells.chunks_mut(width as usize) {
+            row.reverse();
+        }
+    }
+    for _ in 0..turns {
+        let mut turned = vec![false; cells.len()];
+        for y in 0..height {
+            for x in 0..width {
+                // The turned grid is height cells wide
+                turned[(x * height + height - 1 - y) as usize] = cells[(y * width + x) as usize];
+            }
+        }
+        cells = turned;
+        std::mem::swap(&mut width, &mut height);
+    }
+    return (width, height, cells);
+}
+
+/**
+ * The corridor pieces the margins away from the sockets of the room that
+ * can be dug, the room doesn't have to be on the map yet
+ */
+pub(crate) fn socket_stubs(map: &Map, room: &Room, options: &GeneratorOptions) -> Vec<(u32, u32)> {
+    let size = (
+        options.corridor_width as i32,
+        options.corridor_height as i32,
+    );
+    let margins = (options.margins.0 as i32, options.margins.1 as i32);
+    let mut stubs = vec![];
+    for (x, y) in room.cells() {
+        let (x, y) = (x as i32, y as i32);
+        if !room.is_socket(x, y) {
+            continue;
+        }
+        // Every side of the socket that faces out of the room
+        for (d_x, d_y) in [(1, 0), (-1, 0), (0, 1), (0, -1)].iter() {
+            if room.contains(x + d_x, y + d_y) {
+                continue;
+            }
+            let (stub_x, stub_y) = match (d_x, d_y) {
+                (1, _) => (x + 1 + margins.0, y),
+                (-1, _) => (x - margins.0 - size.0, y),
+                (_, 1) => (x, y + 1 + margins.1),
+                _ => (x, y - margins.1 - size.1),
+            };
+            if stub_x >= 0
+                && stub_y >= 0
+                && is_diggable(
+                    map,
+                    stub_x as u32,
+                    stub_y as u32,
+                    (size.0 as u32, size.1 as u32),
+                    options.margins,
+                )
+            {
+                stubs.push((stub_x as u32, stub_y as u32));
+            }
+        }
+    }
+    return stubs;
+}
+
+/**
+ * Digs a corridor piece in front of every socket of the room, so the
+ * section merger has a connection through each of them. Pieces that
+ * would overlap one that's already dug are left out
+ */
+pub(crate) fn dig_socket_stubs(map: &mut Map, room: usize, options: &GeneratorOptions) {
+    let size = (
+        options.corridor_width as u32,
+        options.corridor_height as u32,
+    );
+    for (x, y) in socket_stubs(map, map.get_room(room), options) {
+        if !is_diggable(map, x, y, size, options.margins) {
+            continue;
+        }
+        let corridor_index = map.add_corridor();
+        map.set_rect(Cell::Corridor(corridor_index), x, y, size.0, size.1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_generator::Generator;
+
+    #[test]
+    fn templates_that_cant_be_read() {
+        assert_eq!(Prefab::from_ascii(""), Err(PrefabParseError::Empty));
+        assert_eq!(Prefab::from_ascii("##\n##"), Err(PrefabParseError::Empty));
+        assert_eq!(
+            Prefab::from_ascii("..\n.x"),
+            Err(PrefabParseError::UnknownGlyph {
+                x: 1,
+                y: 1,
+                glyph: 'x'
+            })
+        );
+        assert_eq!(
+            Prefab::from_ascii("...\n.D.\n..."),
+            Err(PrefabParseError::SocketNotOnEdge { x: 1, y: 1 })
+        );
+    }
+
+    #[test]
+    fn rock_around_the_floor_is_cut_off() {
+        let prefab = Prefab::from_ascii("\n   ####\n   #..#\n   #.D\n").unwrap();
+        assert_eq!(prefab.size(), (2, 2));
+        assert_eq!(prefab.footprint, vec![true; 4]);
+        assert_eq!(prefab.sockets, vec![false, false, false, true]);
+    }
+
+    #[test]
+    fn transform_flips_then_turns_clockwise() {
+        // X..
+        // XXX
+        let cells = [true, false, false, true, true, true];
+        let turned = vec![true, true, true, false, true, false];
+        assert_eq!(transform(&cells, (3, 2), false, 1), (2, 3, turned));
+        let flipped = vec![false, false, true, true, true, true];
+        assert_eq!(transform(&cells, (3, 2), true, 0), (3, 2, flipped));
+        let half = vec![true, true, true, false, false, true];
+        assert_eq!(transform(&cells, (3, 2), false, 2), (3, 2, half));
+        assert_eq!(transform(&cells, (3, 2), false, 4), (3, 2, cells.to_vec()));
+    }
+
+    #[test]
+    fn prefab_rooms_have_the_template_footprint() {
+        let template = "
+            .....
+            .....
+            ..
+            ..
+        ";
+        let prefab = Prefab::from_ascii(template).unwrap().rotate(true);
+        for seed in 0..5 {
+            let map = Generator::new()
+                .seed(seed)
+                .prefab(prefab.clone())
+                .prefab_chance(1f32)
+                .generate();
+            assert!(map.iter_rooms().len() > 0);
+            for (idx, room) in map.iter_rooms().enumerate() {
+                assert_eq!(room.prefab, Some(0));
+                assert_eq!(room.cells().count(), 14);
+                for (x, y) in room.cells() {
+                    assert!(map[(x, y)] == Cell::Room(idx), "seed {}", seed);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::cell_matrix::{Cell, Map};
+
+/**
+ * A part of the map that stays solid rock, no rooms, corridors
+ * or walls are built there
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReservedArea {
+    // (x, y, width, height), the part outside of the map is left out
+    Rect(u32, u32, u32, u32),
+    // Row by row, which cells are reserved starting at the top left of the map
+    Mask {
+        width: u32,
+        height: u32,
+        cells: Vec<bool>,
+    },
+}
+
+impl ReservedArea {
+    /**
+     * Masks need a cell for every position of their width and height
+     */
+    pub(crate) fn is_valid(&self) -> bool {
+        match self {
+            ReservedArea::Rect(_, _, _, _) => return true,
+            ReservedArea::Mask {
+                width,
+                height,
+                cells,
+            } => return cells.len() == *width as usize * *height as usize,
+        }
+    }
+    /**
+     * Turns the reserved cells inside of the map into solid rock
+     */
+    pub(crate) fn apply(&self, map: &mut Map) {
+        match self {
+            ReservedArea::Rect(x, y, width, height) => {
+                for pos_y in *y..y.saturating_add(*height).min(map.height) {
+                    for pos_x in *x..x.saturating_add(*width).min(map.width) {
+                        map.set(pos_x, pos_y, Cell::SolidRock);
+                    }
+                }
+            }
+            ReservedArea::Mask {
+                width,
+                height,
+                cells,
+            } => {
+                for pos_y in 0..*height.min(&map.height) {
+                    for pos_x in 0..*width.min(&map.width) {
+                        if cells[pos_y as usize * *width as usize + pos_x as usize] {
+                            map.set(pos_x, pos_y, Cell::SolidRock);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connectivity::DisconnectedPolicy;
+    use crate::error::GenerateError;
+    use crate::map_generator::Generator;
+
+    #[test]
+    fn reserved_cells_stay_solid_rock() {
+        for seed in 0..10 {
+            let map = Generator::new()
+                .seed(seed)
+                .reserve(ReservedArea::Rect(40, 10, 16, 12))
+                .reserve(ReservedArea::Mask {
+                    width: 3,
+                    height: 2,
+                    cells: vec![true, false, true, false, true, false],
+                })
+                .walls(true)
+                .generate();
+            for (x, y, cell) in map.iter() {
+                let in_rect = (40..56).contains(&x) && (10..22).contains(&y);
+                let in_mask = y < 2 && x < 3 && (x + y) % 2 == 0;
+                if in_rect || in_mask {
+                    assert!(*cell == Cell::SolidRock, "({}, {}) seed {}", x, y, seed);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reserved_rect_outside_of_the_map() {
+        let map = Generator::new()
+            .seed(0)
+            .reserve(ReservedArea::Rect(60, 28, u32::MAX, u32::MAX))
+            .reserve(ReservedArea::Rect(100, 100, 4, 4))
+            .generate();
+        assert!(*map.get(63, 31) == Cell::SolidRock);
+    }
+
+    #[test]
+    fn fixed_room_outside_of_the_map() {
+        for room in [(60, 2, 10, 6), (2, 30, 4, 4), (u32::MAX, 2, 10, 6)].iter() {
+            let map = Generator::new()
+                .with_fixed_room(room.0, room.1, room.2, room.3)
+                .try_generate();
+            assert_eq!(
+                map.err(),
+                Some(GenerateError::FixedRoomOutsideMap {
+                    room: *room,
+                    map: (64, 32),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn fixed_room_on_a_reserved_area() {
+        let map = Generator::new()
+            .with_fixed_room(2, 2, 10, 6)
+            .reserve(ReservedArea::Rect(8, 4, 4, 4))
+            .try_generate();
+        assert_eq!(
+            map.err(),
+            Some(GenerateError::FixedRoomBlocked {
+                room: (2, 2, 10, 6)
+            })
+        );
+    }
+
+    #[test]
+    fn fixed_room_walled_in_by_reserved_areas() {
+        for seed in 0..10 {
+            let map = Generator::new()
+                .seed(seed)
+                .with_fixed_room(20, 20, 4, 4)
+                .reserve(ReservedArea::Rect(18, 18, 8, 2))
+                .reserve(ReservedArea::Rect(18, 24, 8, 2))
+                .reserve(ReservedArea::Rect(18, 20, 2, 4))
+                .reserve(ReservedArea::Rect(24, 20, 2, 4))
+                .try_generate();
+            assert_eq!(
+                map.err(),
+                Some(GenerateError::FixedRoomUnreachable {
+                    room: (20, 20, 4, 4)
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn fixed_room_is_connected() {
+        for seed in 0..10 {
+            let map = Generator::new()
+                .seed(seed)
+                .with_fixed_room(20, 20, 4, 4)
+                .disconnected_policy(DisconnectedPolicy::Remove)
+                .generate();
+            assert!(map.iter_rooms().any(|room| room.fixed));
+        }
+    }
+}
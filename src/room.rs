@@ -1,22 +1,409 @@
-use crate::sections::{Section, Sectionable};
+use crate::cave::{largest_region, smooth};
+
+use rand::Rng;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoomShape {
+    Rectangle,
+    // An ellipse touching the edges of the room
+    Ellipse,
+    // A plus sign with arms a third of the room size wide
+    Cross,
+    // A rectangle with one random quarter cut away
+    LShape,
+    // A rectangle with its corners cut diagonally
+    Octagon,
+    // An irregular cave grown with cellular automata
+    Cave,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RoomSizeDistribution {
+    // Width and height are picked evenly between the minimum and maximum room size
+    Uniform,
+    // Sizes gather around the middle of the minimum and maximum room size,
+    // the standard deviation is this share of the range between them
+    Normal(f32),
+    // A size class is picked by its weight and the size evenly inside of it,
+    // the minimum and maximum room size aren't used
+    Weighted(Vec<(SizeClass, u32)>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SizeClass {
+    // (width, height)
+    pub min: (u32, u32),
+    // (width, height)
+    pub max: (u32, u32),
+}
+
+impl RoomShape {
+    /**
+     * Returns which cells of a width * height rectangle are part of the room,
+     * row by row
+     */
+    pub fn footprint<R: Rng>(&self, rng: &mut R, width: u32, height: u32) -> Vec<bool> {
+        let (w, h) = (width as i32, height as i32);
+        let mut cells = Vec::with_capacity((w * h) as usize);
+        match self {
+            RoomShape::Rectangle => cells.resize((w * h) as usize, true),
+            RoomShape::Ellipse => {
+                let r_x = w as f32 / 2f32;
+                let r_y = h as f32 / 2f32;
+                for y in 0..h {
+                    for x in 0..w {
+                        // Measure from the center of the cell
+                        let d_x = (x as f32 + 0.5f32 - r_x) / r_x;
+                        let d_y = (y as f32 + 0.5f32 - r_y) / r_y;
+                        cells.push(d_x * d_x + d_y * d_y <= 1f32);
+                    }
+                }
+            }
+            RoomShape::Cross => {
+                let arm_w = (w / 3).max(1);
+                let arm_h = (h / 3).max(1);
+                let arm_x = (w - arm_w) / 2;
+                let arm_y = (h - arm_h) / 2;
+                for y in 0..h {
+                    for x in 0..w {
+                        cells.push(
+                            (x >= arm_x && x < arm_x + arm_w) || (y >= arm_y && y < arm_y + arm_h),
+                        );
+                    }
+                }
+            }
+            RoomShape::LShape => {
+                let cut_w = w / 2;
+                let cut_h = h / 2;
+                // Which corner to cut away, 0 is top left and continues clockwise
+                let corner = rng.gen_range(0, 4);
+                for y in 0..h {
+                    for x in 0..w {
+                        let in_x = if corner == 0 || corner == 3 {
+                            x < cut_w
+                        } else {
+                            x >= w - cut_w
+                        };
+                        let in_y = if corner < 2 {
+                            y < cut_h
+                        } else {
+                            y >= h - cut_h
+                        };
+                        cells.push(!(in_x && in_y));
+                    }
+                }
+            }
+            RoomShape::Octagon => {
+                let cut = w.min(h) / 3;
+                for y in 0..h {
+                    for x in 0..w {
+                        let d_x = x.min(w - 1 - x);
+                        let d_y = y.min(h - 1 - y);
+                        cells.push(d_x + d_y >= cut);
+                    }
+                }
+            }
+            RoomShape::Cave => {
+                cells = cave_footprint(rng, w, h);
+                if !cells.iter().any(|c| *c) {
+                    // The cave died out, fall back to a smooth shape
+                    return RoomShape::Ellipse.footprint(rng, width, height);
+                }
+            }
+        }
+        return cells;
+    }
+}
+
+fn cave_footprint<R: Rng>(rng: &mut R, w: i32, h: i32) -> Vec<bool> {
+    let mut rock: Vec<bool> = (0..(w * h)).map(|_| rng.gen::<f32>() >= 0.6f32).collect();
+    let fixed = vec![false; rock.len()];
+    smooth(
+        &mut rock,
+        &fixed,
+        (w, h),
+        &[5, 6, 7, 8],
+        &[4, 5, 6, 7, 8],
+        4,
+    );
+    // Only keep the largest connected part of the cave
+    let mut footprint = vec![false; rock.len()];
+    for idx in largest_region(&rock, (w, h)) {
+        footprint[idx] = true;
+    }
+    return footprint;
+}
 
 pub struct Room {
-    pub width: u16,
-    pub height: u16,
-    pub x: u16,
-    pub y: u16,
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
     pub section_id: usize,
+    // Rooms stamped from a prefab are rectangles with the footprint of the template
+    pub shape: RoomShape,
+    // The index of the prefab the room was stamped from
+    pub prefab: Option<usize>,
+    // Placed with `Generator::with_fixed_room`, always connected to the map
+    pub fixed: bool,
+    // Which cells inside of the bounding rectangle belong to the room
+    footprint: Vec<bool>,
+    // Which cells inside of the bounding rectangle doors can be opened from,
+    // empty when doors can be anywhere
+    sockets: Vec<bool>,
 }
 
 impl Room {
-    pub fn collides_with(&self, room: &Room, margins: (u8, u8)) -> bool {
-        return (self.x as i32 - margins.0 as i32) < (room.x + room.width) as i32
-            && room.x < self.x + self.width + margins.0 as u16
-            && (self.y as i32 - margins.1 as i32) < (room.y + room.height) as i32
-            && room.y < self.y + self.height + margins.1 as u16;
+    pub fn new<R: Rng>(
+        rng: &mut R,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        shape: RoomShape,
+        section_id: usize,
+    ) -> Room {
+        return Room {
+            width,
+            height,
+            x,
+            y,
+            section_id,
+            shape,
+            prefab: None,
+            fixed: false,
+            footprint: shape.footprint(rng, width, height),
+            sockets: vec![],
+        };
+    }
+    /**
+     * Creates a room from the cells of its bounding rectangle, row by row
+     */
+    pub(crate) fn from_footprint(
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        shape: RoomShape,
+        footprint: Vec<bool>,
+        section_id: usize,
+    ) -> Room {
+        return Room {
+            width,
+            height,
+            x,
+            y,
+            section_id,
+            shape,
+            prefab: None,
+            fixed: false,
+            footprint,
+            sockets: vec![],
+        };
+    }
+    /**
+     * Creates a room at the top left of the map from a prefab template,
+     * the footprint and sockets are row by row
+     */
+    pub(crate) fn from_prefab(
+        width: u32,
+        height: u32,
+        footprint: Vec<bool>,
+        sockets: Vec<bool>,
+        prefab: usize,
+    ) -> Room {
+        return Room {
+            width,
+            height,
+            x: 0,
+            y: 0,
+            section_id: 0,
+            shape: RoomShape::Rectangle,
+            prefab: Some(prefab),
+            fixed: false,
+            footprint,
+            sockets,
+        };
+    }
+    /**
+     * Returns true if the map position is part of the room footprint
+     */
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        let local_x = x - self.x as i32;
+        let local_y = y - self.y as i32;
+        if local_x < 0
+            || local_y < 0
+            || local_x >= self.width as i32
+            || local_y >= self.height as i32
+        {
+            return false;
+        }
+        return self.footprint[(local_y * self.width as i32 + local_x) as usize];
+    }
+    /**
+     * Whether doors can only be opened from the sockets of the room
+     */
+    pub fn has_sockets(&self) -> bool {
+        return self.sockets.iter().any(|s| *s);
+    }
+    /**
+     * Returns true if the map position is a socket of the room
+     */
+    pub fn is_socket(&self, x: i32, y: i32) -> bool {
+        if !self.has_sockets() || !self.contains(x, y) {
+            return false;
+        }
+        let (local_x, local_y) = (x - self.x as i32, y - self.y as i32);
+        return self.sockets[(local_y * self.width as i32 + local_x) as usize];
+    }
+    /**
+     * Iterates over the map positions of every cell in the room footprint
+     */
+    pub fn cells(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        return self
+            .footprint
+            .iter()
+            .enumerate()
+            .filter(|(_, in_room)| **in_room)
+            .map(move |(i, _)| {
+                (
+                    self.x + (i % self.width as usize) as u32,
+                    self.y + (i / self.width as usize) as u32,
+                )
+            });
+    }
+    /**
+     * Returns the first and last (exclusive) x position of the footprint on a row
+     */
+    pub fn row_span(&self, y: i32) -> Option<(i32, i32)> {
+        let xs = (self.x as i32..(self.x + self.width) as i32).filter(|x| self.contains(*x, y));
+        return span(xs);
+    }
+    /**
+     * Returns the first and last (exclusive) y position of the footprint on a column
+     */
+    pub fn column_span(&self, x: i32) -> Option<(i32, i32)> {
+        let ys = (self.y as i32..(self.y + self.height) as i32).filter(|y| self.contains(x, *y));
+        return span(ys);
+    }
+    /**
+     * Returns a copy of the room with every cell moved by the transform,
+     * used to mirror and rotate rooms
+     */
+    pub fn transformed<F>(&self, transform: F, section_id: usize) -> Room
+    where
+        F: Fn(u32, u32) -> (u32, u32),
+    {
+        let cells: Vec<(u32, u32)> = self.cells().map(|(x, y)| transform(x, y)).collect();
+        let sockets: Vec<(u32, u32)> = self
+            .cells()
+            .filter(|(x, y)| self.is_socket(*x as i32, *y as i32))
+            .map(|(x, y)| transform(x, y))
+            .collect();
+        // Opposite corners of the bounding rectangle stay opposite corners
+        let corner = transform(self.x, self.y);
+        let opposite = transform(self.x + self.width - 1, self.y + self.height - 1);
+        let x = corner.0.min(opposite.0);
+        let y = corner.1.min(opposite.1);
+        let width = corner.0.max(opposite.0) - corner.0.min(opposite.0) + 1;
+        let height = corner.1.max(opposite.1) - corner.1.min(opposite.1) + 1;
+        let mut footprint = vec![false; width as usize * height as usize];
+        for (cell_x, cell_y) in cells {
+            footprint[(cell_y - y) as usize * width as usize + (cell_x - x) as usize] = true;
+        }
+        let mut socket_cells = vec![];
+        if !sockets.is_empty() {
+            socket_cells = vec![false; footprint.len()];
+            for (cell_x, cell_y) in sockets {
+                socket_cells[(cell_y - y) as usize * width as usize + (cell_x - x) as usize] = true;
+            }
+        }
+        return Room {
+            width,
+            height,
+            x,
+            y,
+            section_id,
+            shape: self.shape,
+            prefab: self.prefab,
+            fixed: self.fixed,
+            footprint,
+            sockets: socket_cells,
+        };
     }
 }
 
+fn span<I: Iterator<Item = i32>>(mut positions: I) -> Option<(i32, i32)> {
+    let first = positions.next()?;
+    let last = positions.last().unwrap_or(first);
+    return Some((first, last + 1));
+}
+
 pub struct Corridor {
     pub section_id: usize,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cave::regions;
+    use crate::cell_matrix::Cell;
+    use crate::map_generator::Generator;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const SHAPES: [RoomShape; 6] = [
+        RoomShape::Rectangle,
+        RoomShape::Ellipse,
+        RoomShape::Cross,
+        RoomShape::LShape,
+        RoomShape::Octagon,
+        RoomShape::Cave,
+    ];
+
+    #[test]
+    fn footprints_are_one_piece() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for shape in SHAPES.iter() {
+            for (width, height) in [(3, 3), (7, 5), (12, 9)].iter() {
+                let footprint = shape.footprint(&mut rng, *width, *height);
+                assert_eq!(footprint.len(), (width * height) as usize);
+                let rock: Vec<bool> = footprint.iter().map(|floor| !floor).collect();
+                let size = (*width as i32, *height as i32);
+                assert_eq!(regions(&rock, size).len(), 1, "{:?}", shape);
+            }
+        }
+    }
+
+    #[test]
+    fn shapes_cut_their_corners() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(RoomShape::Rectangle
+            .footprint(&mut rng, 9, 9)
+            .iter()
+            .all(|floor| *floor));
+        for shape in [RoomShape::Ellipse, RoomShape::Cross, RoomShape::Octagon].iter() {
+            let footprint = shape.footprint(&mut rng, 9, 9);
+            assert!(!footprint[0] && !footprint[8] && !footprint[72] && !footprint[80]);
+            assert!(footprint[40]);
+        }
+        let l_shape = RoomShape::LShape.footprint(&mut rng, 9, 9);
+        assert_eq!(l_shape.iter().filter(|floor| !**floor).count(), 16);
+    }
+
+    #[test]
+    fn room_cells_are_inside_of_the_footprint() {
+        for seed in 0..5 {
+            let map = Generator::new()
+                .seed(seed)
+                .room_shapes(SHAPES.to_vec())
+                .generate();
+            for (x, y, cell) in map.iter() {
+                if let Cell::Room(room) = cell {
+                    assert!(map.get_room(*room).contains(x as i32, y as i32));
+                }
+            }
+        }
+    }
+}
@@ -1,89 +1,459 @@
+use crate::bsp::partition;
 use crate::cell_matrix::{Cell, Map};
-use crate::map_generator::MapShape;
-use crate::room::Room;
-use crate::sections::Section;
+use crate::error::GenerateError;
+use crate::map_generator::{GeneratorOptions, RoomPlacement};
+use crate::map_shape::MapShape;
+use crate::prefab::{dig_socket_stubs, roll_prefab, socket_stubs};
+use crate::room::{Room, RoomShape, RoomSizeDistribution, SizeClass};
+use crate::separation::separate;
 
-use rand::thread_rng;
+use rand::distributions::{Distribution, Normal};
 use rand::Rng;
 
-use std::cmp;
+const PI_2: f32 = std::f32::consts::PI * 2f32;
+// Failed attempts in a row after which random placement gives up on the room targets
+const MAX_FAILED_ATTEMPTS: u32 = 256;
 
-const PI_2: f32 = 3.141592 * 2f32;
+/**
+ * Places the rooms, returns an error when the rooms miss the minimum room
+ * count or the room coverage
+ */
+pub fn generate_rooms<R: Rng>(
+    rng: &mut R,
+    map: &mut Map,
+    options: &GeneratorOptions,
+) -> Result<(), GenerateError> {
+    place_fixed_rooms(map, options)?;
+    match &options.room_placement {
+        RoomPlacement::Random => place_randomly(rng, map, options),
+        RoomPlacement::Bsp(bsp) => partition(rng, map, bsp, options),
+        RoomPlacement::Separation(separation) => separate(rng, map, separation, options),
+    }
+    let rooms = map.iter_rooms().len();
+    let room_cells = map.cell_vector.iter().filter(|c| c.is_room()).count();
+    let coverage = room_cells as f32 / floor_cells(map).max(1) as f32;
+    if rooms < options.min_rooms as usize || coverage < options.room_coverage {
+        return Err(GenerateError::RoomTargetsMissed { rooms, coverage });
+    }
+    return Ok(());
+}
+
+/**
+ * Stamps the fixed rooms before any other room, returns an error when one
+ * of them isn't on rock inside of the map shape
+ */
+fn place_fixed_rooms(map: &mut Map, options: &GeneratorOptions) -> Result<(), GenerateError> {
+    for (x, y, width, height) in options.fixed_rooms.iter() {
+        if map
+            .rect_is(*x as i32, *y as i32, *width, *height, |c| !c.is_rock())
+            .is_some()
+        {
+            return Err(GenerateError::FixedRoomBlocked {
+                room: (*x, *y, *width, *height),
+            });
+        }
+        let footprint = vec![true; *width as usize * *height as usize];
+        let mut room =
+            Room::from_footprint(*x, *y, *width, *height, RoomShape::Rectangle, footprint, 0);
+        room.fixed = true;
+        add_room(map, room, options);
+    }
+    return Ok(());
+}
 
-fn is_within_circle_shape(a: &Room, radius: u16) -> bool {
-    let dx = cmp::max(
-        radius as i16 - a.x as i16,
-        (a.x + a.width) as i16 - radius as i16,
-    ) as i32;
-    let dy = cmp::max(
-        radius as i16 - a.y as i16,
-        (a.y + a.height) as i16 - radius as i16,
-    ) as i32;
-    return radius as i32 * radius as i32 >= dx * dx + dy * dy;
+/**
+ * Whether the map already has the maximum amount of rooms
+ */
+pub(crate) fn room_limit_reached(map: &Map, options: &GeneratorOptions) -> bool {
+    return match options.max_rooms {
+        Some(max_rooms) => map.iter_rooms().len() >= max_rooms as usize,
+        None => false,
+    };
 }
 
-pub fn generate_rooms(
-    map: &mut Map,
-    room_min: (u16, u16),
-    room_max: (u16, u16),
-    margins: (u8, u8),
-    iterations: u32,
-    shape: MapShape,
-) {
-    for _ in 0..iterations {
-        let room_width = thread_rng().gen_range(room_min.0, room_max.0 + 1);
-        let room_height = thread_rng().gen_range(room_min.1, room_max.1 + 1);
-
-        let (x, y) = match shape {
+/**
+ * Puts the room on the map as its own section, rooms with sockets get a
+ * corridor piece in front of their sockets
+ */
+pub(crate) fn add_room(map: &mut Map, mut room: Room, options: &GeneratorOptions) {
+    room.section_id = map.new_section();
+    let idx = map.push_room(room);
+    for (x_pos, y_pos) in map.get_room(idx).cells().collect::<Vec<_>>() {
+        map.set(x_pos, y_pos, Cell::Room(idx))
+    }
+    if map.get_room(idx).has_sockets() {
+        dig_socket_stubs(map, idx, options);
+    }
+}
+
+/**
+ * Picks a room size from the size distribution, the longer side is cut down
+ * to the maximum aspect ratio, where that would go below the minimum size the
+ * shorter side is grown instead
+ */
+pub(crate) fn room_size<R: Rng>(rng: &mut R, options: &GeneratorOptions) -> (u32, u32) {
+    let (room_min, room_max) = (options.room_min, options.room_max);
+    let (mut width, mut height, min, max) = match &options.room_size_distribution {
+        RoomSizeDistribution::Uniform => (
+            rng.gen_range(room_min.0, room_max.0 + 1),
+            rng.gen_range(room_min.1, room_max.1 + 1),
+            room_min,
+            room_max,
+        ),
+        RoomSizeDistribution::Normal(deviation) => (
+            normal_size(rng, room_min.0, room_max.0, *deviation),
+            normal_size(rng, room_min.1, room_max.1, *deviation),
+            room_min,
+            room_max,
+        ),
+        RoomSizeDistribution::Weighted(classes) => {
+            let class = pick_class(rng, classes);
+            (
+                rng.gen_range(class.min.0, class.max.0 + 1),
+                rng.gen_range(class.min.1, class.max.1 + 1),
+                class.min,
+                class.max,
+            )
+        }
+    };
+    match options.max_aspect_ratio {
+        Some(ratio) => {
+            if width as f32 > height as f32 * ratio {
+                width = ((height as f32 * ratio) as u32).max(min.0);
+                if width as f32 > height as f32 * ratio {
+                    height = ((width as f32 / ratio).ceil() as u32).min(max.1);
+                }
+            } else if height as f32 > width as f32 * ratio {
+                height = ((width as f32 * ratio) as u32).max(min.1);
+                if height as f32 > width as f32 * ratio {
+                    width = ((height as f32 / ratio).ceil() as u32).min(max.0);
+                }
+            }
+        }
+        None => {}
+    }
+    return (width, height);
+}
+
+/**
+ * Whether a room between the minimum and maximum size can have the aspect ratio
+ */
+pub(crate) fn allows_aspect_ratio(min: (u32, u32), max: (u32, u32), ratio: f32) -> bool {
+    return min.0 as f32 <= max.1 as f32 * ratio && min.1 as f32 <= max.0 as f32 * ratio;
+}
+
+/**
+ * The smallest size a room can be picked with
+ */
+pub(crate) fn smallest_room_size(options: &GeneratorOptions) -> (u32, u32) {
+    return match &options.room_size_distribution {
+        RoomSizeDistribution::Weighted(classes) => classes
+            .iter()
+            .map(|(class, _)| class.min)
+            .fold((u32::MAX, u32::MAX), |(w, h), min| {
+                (w.min(min.0), h.min(min.1))
+            }),
+        _ => options.room_min,
+    };
+}
+
+fn normal_size<R: Rng>(rng: &mut R, min: u32, max: u32, deviation: f32) -> u32 {
+    let normal = Normal::new(
+        (min + max) as f64 / 2f64,
+        deviation as f64 * (max - min) as f64,
+    );
+    let size = normal.sample(rng).round() as i64;
+    return size.max(min as i64).min(max as i64) as u32;
+}
+
+fn pick_class<R: Rng>(rng: &mut R, classes: &[(SizeClass, u32)]) -> SizeClass {
+    let total_weight: u32 = classes.iter().map(|(_, weight)| weight).sum();
+    let mut roll = rng.gen_range(0, total_weight);
+    for (class, weight) in classes {
+        if roll < *weight {
+            return *class;
+        }
+        roll -= weight;
+    }
+    return classes[classes.len() - 1].0;
+}
+
+// The cells inside of the map shape
+fn floor_cells(map: &Map) -> usize {
+    return map
+        .cell_vector
+        .iter()
+        .filter(|c| **c != Cell::SolidRock)
+        .count();
+}
+
+fn place_randomly<R: Rng>(rng: &mut R, map: &mut Map, options: &GeneratorOptions) {
+    let room_shapes = &options.room_shapes;
+    let target_cells = floor_cells(map) as f32 * options.room_coverage;
+    let mut room_cells = 0;
+    let mut attempts = 0;
+    let mut failures = 0;
+    while !room_limit_reached(map, options) {
+        let targets_met = map.iter_rooms().len() >= options.min_rooms as usize
+            && room_cells as f32 >= target_cells;
+        // Past the iterations rooms are only placed to meet the room count and coverage
+        if attempts >= options.iterations && (targets_met || failures >= MAX_FAILED_ATTEMPTS) {
+            break;
+        }
+        attempts += 1;
+        match roll_prefab(rng, options) {
+            Some(mut room) if room.width <= map.width && room.height <= map.height => {
+                room.x = rng.gen_range(0, map.width - room.width + 1);
+                room.y = rng.gen_range(0, map.height - room.height + 1);
+                if room_fits(map, &room, options) {
+                    room_cells += room.cells().count();
+                    add_room(map, room, options);
+                    failures = 0;
+                } else {
+                    failures += 1;
+                }
+                continue;
+            }
+            _ => {}
+        }
+        let room_shape = room_shapes[rng.gen_range(0, room_shapes.len())];
+        let (room_width, room_height) = room_size(rng, options);
+
+        let (x, y) = match options.shape {
             MapShape::Circle => {
                 // TODO: why - 4?
-                let width = map.width - 4;
-                let height = map.height - 4;
+                let width = map.width.saturating_sub(4);
+                let height = map.height.saturating_sub(4);
 
-                let angle = thread_rng().gen::<f32>() * PI_2;
-                let mut rng = thread_rng();
-                let r_x = rng.gen::<f32>() * ((width - room_width) as f32 / 2f32);
-                let r_y = rng.gen::<f32>() * ((height - room_height) as f32 / 2f32);
+                let angle = rng.gen::<f32>() * PI_2;
+                let r_x = rng.gen::<f32>() * (width.saturating_sub(room_width) as f32 / 2f32);
+                let r_y = rng.gen::<f32>() * (height.saturating_sub(room_height) as f32 / 2f32);
                 (
-                    (width as f32 / 2f32 + r_x * (angle.cos())).floor() as u16,
-                    (height as f32 / 2f32 + r_y * (angle.sin())).floor() as u16,
+                    (width as f32 / 2f32 + r_x * (angle.cos())).floor() as u32,
+                    (height as f32 / 2f32 + r_y * (angle.sin())).floor() as u32,
                 )
             }
             _ => (
-                thread_rng().gen_range(0, map.width - room_width + 1),
-                thread_rng().gen_range(0, map.height - room_height + 1),
+                rng.gen_range(0, map.width - room_width + 1),
+                rng.gen_range(0, map.height - room_height + 1),
             ),
         };
 
-        let room = Room {
-            width: room_width,
-            height: room_height,
-            x,
-            y,
-            section_id: map.new_section(),
-        };
-        // Check that the room doesn't collide with another room object by object
-        // This implementation is used as we _could_ check it room per room,
-        // but then other cells couldn't block future rooms.
-        // And when the room sizes are small enough, it doesn't make that
-        // big of a difference
-        match map.rect_is(
+        let room = Room::new(rng, x, y, room_width, room_height, room_shape, 0);
+        if room_fits(map, &room, options) {
+            // Nothing of note at the rooms location, put it there
+            room_cells += room.cells().count();
+            add_room(map, room, options);
+            failures = 0;
+        } else {
+            failures += 1;
+        }
+    }
+}
+
+/**
+ * Check that the room doesn't collide with another room object by object
+ * This implementation is used as we _could_ check it room per room,
+ * but then other cells couldn't block future rooms.
+ * And when the room sizes are small enough, it doesn't make that
+ * big of a difference.
+ * Cells outside of the map shape are solid rock so the room
+ * is kept within the shape as well. Rooms with sockets need space
+ * for a corridor in front of one of them.
+ */
+pub(crate) fn room_fits(map: &Map, room: &Room, options: &GeneratorOptions) -> bool {
+    if room.has_sockets() && socket_stubs(map, room, options).is_empty() {
+        return false;
+    }
+    let margins = options.margins;
+    let is_blocked = |c: &Cell| *c != Cell::Rock;
+    // If the bounding rectangle is free the footprint is free as well
+    if map
+        .rect_is(
+            room.x as i32 - margins.0 as i32,
+            room.y as i32 - margins.1 as i32,
+            room.width + margins.0 as u32 * 2,
+            room.height + margins.1 as u32 * 2,
+            is_blocked,
+        )
+        .is_none()
+    {
+        return true;
+    }
+    if room.shape == RoomShape::Rectangle && room.prefab.is_none() {
+        return false;
+    }
+    return room.cells().all(|(x, y)| {
+        map.rect_is(
             x as i32 - margins.0 as i32,
             y as i32 - margins.1 as i32,
-            room_width + (margins.0 * 2) as u16,
-            room_height + (margins.1 * 2) as u16,
-            |c| *c != Cell::Rock,
-        ) {
-            Some(_) => {}
-            _ => {
-                // Nothing of note at the rooms location, put it there
-                let idx = map.push_room(room);
-                for x_pos in x..(x + room_width) {
-                    for y_pos in y..(y + room_height) {
-                        map.set(x_pos, y_pos, Cell::Room(idx))
-                    }
-                }
-            }
+            margins.0 as u32 * 2 + 1,
+            margins.1 as u32 * 2 + 1,
+            is_blocked,
+        )
+        .is_none()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::GenerateError;
+    use crate::map_generator::Generator;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /**
+     * The share of the cells inside of the map shape that are rooms
+     */
+    fn coverage(map: &Map) -> f32 {
+        let rooms = map.iter().filter(|(_, _, c)| c.is_room()).count();
+        return rooms as f32 / floor_cells(map) as f32;
+    }
+
+    #[test]
+    fn margins_wider_than_a_u8_doubled() {
+        for margins in [(200, 1), (1, 200), (255, 255)].iter() {
+            let map = Generator::new()
+                .seed(0)
+                .size(30, 20)
+                .iterations(16)
+                .margins(margins.0, margins.1)
+                .try_generate();
+            assert!(map.is_ok());
         }
     }
+
+    #[test]
+    fn room_count_stays_within_the_limits() {
+        for seed in 0..10 {
+            let few = Generator::new().seed(seed).max_rooms(3).generate();
+            assert!(few.iter_rooms().len() <= 3, "seed {}", seed);
+            let many = Generator::new()
+                .seed(seed)
+                .iterations(1)
+                .min_rooms(12)
+                .generate();
+            assert!(many.iter_rooms().len() >= 12, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn rooms_reach_the_coverage() {
+        for seed in 0..10 {
+            let map = Generator::new()
+                .seed(seed)
+                .iterations(1)
+                .room_coverage(0.2)
+                .generate();
+            assert!(coverage(&map) >= 0.2, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn room_targets_that_dont_fit() {
+        let map = Generator::new()
+            .seed(0)
+            .size(20, 20)
+            .min_rooms(50)
+            .try_generate();
+        match map {
+            Err(GenerateError::RoomTargetsMissed { rooms, .. }) => assert!(rooms < 50),
+            _ => panic!("the room targets can't be met"),
+        }
+    }
+
+    #[test]
+    fn invalid_room_targets() {
+        let map = Generator::new().min_rooms(5).max_rooms(3).try_generate();
+        assert_eq!(
+            map.err(),
+            Some(GenerateError::InvalidRoomCount { min: 5, max: 3 })
+        );
+        let map = Generator::new().room_coverage(1.5).try_generate();
+        assert_eq!(map.err(), Some(GenerateError::InvalidRoomCoverage(1.5)));
+    }
+
+    /**
+     * Picks a lot of room sizes and checks each of them
+     */
+    fn check_sizes<F: Fn((u32, u32))>(generator: Generator, check: F) {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            check(room_size(&mut rng, &generator.options));
+        }
+    }
+
+    #[test]
+    fn sizes_stay_within_the_distribution() {
+        let within = |(width, height): (u32, u32)| {
+            assert!((4..=10).contains(&width) && (3..=7).contains(&height));
+        };
+        for distribution in [
+            RoomSizeDistribution::Uniform,
+            RoomSizeDistribution::Normal(0.3),
+        ]
+        .iter()
+        {
+            let generator = Generator::new()
+                .room_size((4, 3), (10, 7))
+                .room_size_distribution(distribution.clone());
+            check_sizes(generator, within);
+        }
+        let classes = vec![
+            (
+                SizeClass {
+                    min: (3, 3),
+                    max: (4, 4),
+                },
+                3,
+            ),
+            (
+                SizeClass {
+                    min: (12, 10),
+                    max: (14, 12),
+                },
+                1,
+            ),
+        ];
+        let generator =
+            Generator::new().room_size_distribution(RoomSizeDistribution::Weighted(classes));
+        check_sizes(generator, |(width, height)| {
+            let small = width <= 4 && height <= 4;
+            let large = (12..=14).contains(&width) && (10..=12).contains(&height);
+            assert!(small || large, "{}x{}", width, height);
+        });
+    }
+
+    #[test]
+    fn sizes_keep_the_aspect_ratio() {
+        let generator = Generator::new()
+            .room_size((8, 3), (12, 8))
+            .max_aspect_ratio(1.5);
+        check_sizes(generator, |(width, height)| {
+            assert!((8..=12).contains(&width) && (3..=8).contains(&height));
+            assert!(width as f32 <= height as f32 * 1.5, "{}x{}", width, height);
+            assert!(height as f32 <= width as f32 * 1.5, "{}x{}", width, height);
+        });
+    }
+
+    #[test]
+    fn invalid_room_sizes() {
+        let map = Generator::new()
+            .room_size((8, 3), (12, 4))
+            .max_aspect_ratio(1.5)
+            .try_generate();
+        assert_eq!(map.err(), Some(GenerateError::InvalidAspectRatio(1.5)));
+        let map = Generator::new().max_aspect_ratio(0.5).try_generate();
+        assert_eq!(map.err(), Some(GenerateError::InvalidAspectRatio(0.5)));
+        let map = Generator::new()
+            .room_size_distribution(RoomSizeDistribution::Normal(-1f32))
+            .try_generate();
+        assert_eq!(map.err(), Some(GenerateError::InvalidRoomDeviation(-1f32)));
+        let map = Generator::new()
+            .room_size_distribution(RoomSizeDistribution::Weighted(vec![]))
+            .try_generate();
+        assert_eq!(map.err(), Some(GenerateError::NoRoomSizeClasses));
+    }
 }
@@ -1,12 +1,23 @@
 use crate::cell_matrix::{Cell, Map};
+use crate::connectivity::{ensure_connected, DisconnectedPolicy};
 use crate::corridor_tree::{get_parent, remove_node, WrappedCorridorNode};
+use crate::dead_ends::{remove_dead_ends, DeadEndRemoval};
 use crate::direction::Direction;
+use crate::doors::{place_doors, DoorKind};
+use crate::error::GenerateError;
+use crate::map_generator::GeneratorOptions;
 use crate::room::Room;
 
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use std::collections::{HashSet, VecDeque};
+use std::rc::Rc;
+
 #[derive(Clone, Copy, PartialEq)]
 pub struct Connection {
-    pub x: u16,
-    pub y: u16,
+    pub x: u32,
+    pub y: u32,
     pub id: usize,
     pub score: f32,
     pub direction: Direction,
@@ -29,11 +40,6 @@ pub struct Section {
     pub connections: Vec<Connection>,
 }
 
-pub trait Sectionable {
-    fn get_section(&self) -> &Section;
-    fn get_section_mut(&mut self) -> &mut Section;
-}
-
 impl Section {
     pub fn new(id: usize) -> Self {
         return Section {
@@ -47,7 +53,7 @@ impl Section {
     pub fn get_id(&self) -> usize {
         return self.id;
     }
-    pub fn add_connection(&mut self, x: u16, y: u16, id: usize, score: f32, direction: Direction) {
+    pub fn add_connection(&mut self, x: u32, y: u32, id: usize, score: f32, direction: Direction) {
         self.connections.push(Connection {
             x,
             y,
@@ -67,120 +73,231 @@ impl PartialEq for Section {
     }
 }
 
-pub struct SectionMerger {
+pub struct SectionMerger<'a, R: Rng> {
+    rng: &'a mut R,
     map: Map,
     margins: (u8, u8),
     corridor_size: (u8, u8),
     prune_length: u32,
+    dead_end_removal: DeadEndRemoval,
+    disconnected_policy: DisconnectedPolicy,
+    door_width: u8,
+    door_kinds: Vec<(DoorKind, u32)>,
+    // Every connection that has been opened, with the size of its opening
+    opened_connections: Vec<(Connection, (u32, u32))>,
+    extra_connection_chance: f32,
+    loop_min_distance: u32,
+    secret_passage_share: f32,
 }
 
-impl SectionMerger {
-    pub fn new(map: Map, margins: (u8, u8), corridor_size: (u8, u8), prune_length: u32) -> Self {
+impl<'a, R: Rng> SectionMerger<'a, R> {
+    pub fn new(rng: &'a mut R, map: Map, options: &GeneratorOptions) -> Self {
         return SectionMerger {
+            rng,
             map,
-            margins,
-            corridor_size,
-            prune_length,
+            margins: options.margins,
+            corridor_size: (options.corridor_width, options.corridor_height),
+            prune_length: options.prune_length,
+            dead_end_removal: options.dead_end_removal,
+            disconnected_policy: options.disconnected_policy,
+            door_width: options.door_width,
+            door_kinds: options.door_kinds.clone(),
+            opened_connections: vec![],
+            extra_connection_chance: options.extra_connection_chance,
+            loop_min_distance: options.loop_min_distance,
+            secret_passage_share: options.secret_passage_share,
         };
     }
-    pub fn generate(mut self) -> Map {
-        // First build the connection matrix
-        for (cell, x, y) in self.map.iter_enumerate() {
+    /**
+     * Every place between two sections where a corridor can be opened,
+     * with the position of the section each connection belongs to
+     */
+    fn find_connections(&self) -> Vec<((i32, i32), Connection)> {
+        let mut connections = vec![];
+        for (x, y, cell) in self.map.iter() {
             if cell.is_rock() {
                 // there's enough room here
                 let left = self
                     .map
-                    .get_section(x as i32 - self.corridor_size.0 as i32, y.into());
+                    .get_section(x as i32 - self.corridor_size.0 as i32, y as i32);
                 let right = self
                     .map
-                    .get_section(x as i32 + (self.margins.0) as i32, y.into());
-                if left != None
-                    && right != None
-                    && left != right
-                    && self.is_section_with_margin(
-                        x as i32 - self.corridor_size.0 as i32,
-                        y.into(),
-                        left.unwrap(),
-                    )
-                    && self.is_section_with_margin(
-                        x as i32 + (self.margins.0) as i32,
-                        y.into(),
-                        right.unwrap(),
-                    )
-                {
-                    // we know that these ID's are correct currently,
-                    let left_id = left.unwrap().get_id();
-                    let right_id = right.unwrap().get_id();
-                    // There is a horizontal connection
-                    let left_score =
-                        self.score_pos(x as i32 - self.corridor_size.0 as i32, y.into(), false);
-                    let right_score =
-                        self.score_pos(x as i32 + self.margins.0 as i32, y.into(), false);
-                    self.map
-                        .get_section_mut(x as i32 - self.corridor_size.0 as i32, y.into())
-                        .unwrap()
-                        .add_connection(x, y, right_id, left_score.min(right_score), Direction::E);
-                    self.map
-                        .get_section_mut(x as i32 + self.margins.0 as i32, y.into())
-                        .unwrap()
-                        .add_connection(x, y, left_id, left_score.min(right_score), Direction::W);
-                    self.map.set(x, y, Cell::Rock);
+                    .get_section(x as i32 + (self.margins.0) as i32, y as i32);
+                if let (Some(left), Some(right)) = (left, right) {
+                    if left != right
+                        && self.is_carvable(
+                            x as i32,
+                            y as i32,
+                            self.margins.0 as u32,
+                            self.corridor_size.1 as u32,
+                        )
+                        && self.is_section_with_margin(
+                            x as i32 - self.corridor_size.0 as i32,
+                            y as i32,
+                            left,
+                        )
+                        && self.is_section_with_margin(
+                            x as i32 + (self.margins.0) as i32,
+                            y as i32,
+                            right,
+                        )
+                        && self.is_socket_side(x as i32 - 1, y as i32, 1, self.corridor_size.1)
+                        && self.is_socket_side(
+                            x as i32 + self.margins.0 as i32,
+                            y as i32,
+                            1,
+                            self.corridor_size.1,
+                        )
+                    {
+                        // we know that these ID's are correct currently,
+                        let left_id = left.get_id();
+                        let right_id = right.get_id();
+                        // There is a horizontal connection
+                        let left_score =
+                            self.score_pos(x as i32 - self.corridor_size.0 as i32, y as i32, false);
+                        let right_score =
+                            self.score_pos(x as i32 + self.margins.0 as i32, y as i32, false);
+                        let score = left_score.min(right_score);
+                        connections.push((
+                            (x as i32 - self.corridor_size.0 as i32, y as i32),
+                            Connection {
+                                x,
+                                y,
+                                id: right_id,
+                                score,
+                                direction: Direction::E,
+                            },
+                        ));
+                        connections.push((
+                            (x as i32 + self.margins.0 as i32, y as i32),
+                            Connection {
+                                x,
+                                y,
+                                id: left_id,
+                                score,
+                                direction: Direction::W,
+                            },
+                        ));
+                    }
                 }
                 let top = self
                     .map
-                    .get_section(x.into(), y as i32 - self.corridor_size.1 as i32 as i32);
+                    .get_section(x as i32, y as i32 - self.corridor_size.1 as i32);
                 let bottom = self
                     .map
-                    .get_section(x.into(), y as i32 + (self.margins.1) as i32);
-                if top != None
-                    && bottom != None
-                    && top != bottom
-                    && self.is_section_with_margin(
-                        x.into(),
-                        y as i32 - self.corridor_size.1 as i32,
-                        top.unwrap(),
-                    )
-                    && self.is_section_with_margin(
-                        x.into(),
-                        y as i32 + (self.margins.1) as i32,
-                        bottom.unwrap(),
-                    )
-                {
-                    // There is a vertical connection
-                    // we know that these ID's are correct currently,
-                    let top_id = top.unwrap().get_id();
-                    let bottom_id = bottom.unwrap().get_id();
-
-                    let top_score =
-                        self.score_pos(x.into(), y as i32 - self.corridor_size.1 as i32, true);
-                    let bottom_score =
-                        self.score_pos(x.into(), y as i32 + self.margins.1 as i32, true);
-                    self.map
-                        .get_section_mut(x.into(), y as i32 - self.corridor_size.1 as i32)
-                        .unwrap()
-                        .add_connection(x, y, bottom_id, top_score.min(bottom_score), Direction::S);
-                    self.map
-                        .get_section_mut(x.into(), y as i32 + self.margins.1 as i32)
-                        .unwrap()
-                        .add_connection(x, y, top_id, top_score.min(bottom_score), Direction::N);
+                    .get_section(x as i32, y as i32 + (self.margins.1) as i32);
+                if let (Some(top), Some(bottom)) = (top, bottom) {
+                    if top != bottom
+                        && self.is_carvable(
+                            x as i32,
+                            y as i32,
+                            self.corridor_size.0 as u32,
+                            self.margins.1 as u32,
+                        )
+                        && self.is_section_with_margin(
+                            x as i32,
+                            y as i32 - self.corridor_size.1 as i32,
+                            top,
+                        )
+                        && self.is_section_with_margin(
+                            x as i32,
+                            y as i32 + (self.margins.1) as i32,
+                            bottom,
+                        )
+                        && self.is_socket_side(x as i32, y as i32 - 1, self.corridor_size.0, 1)
+                        && self.is_socket_side(
+                            x as i32,
+                            y as i32 + self.margins.1 as i32,
+                            self.corridor_size.0,
+                            1,
+                        )
+                    {
+                        // There is a vertical connection
+                        // we know that these ID's are correct currently,
+                        let top_id = top.get_id();
+                        let bottom_id = bottom.get_id();
+
+                        let top_score =
+                            self.score_pos(x as i32, y as i32 - self.corridor_size.1 as i32, true);
+                        let bottom_score =
+                            self.score_pos(x as i32, y as i32 + self.margins.1 as i32, true);
+                        let score = top_score.min(bottom_score);
+                        connections.push((
+                            (x as i32, y as i32 - self.corridor_size.1 as i32),
+                            Connection {
+                                x,
+                                y,
+                                id: bottom_id,
+                                score,
+                                direction: Direction::S,
+                            },
+                        ));
+                        connections.push((
+                            (x as i32, y as i32 + self.margins.1 as i32),
+                            Connection {
+                                x,
+                                y,
+                                id: top_id,
+                                score,
+                                direction: Direction::N,
+                            },
+                        ));
+                    }
                 }
             }
         }
-        // Go through and mark all section as the same section and throw away
-        // unconnected sections
-        let best_section_id = self.connect_sections();
+        return connections;
+    }
+    pub fn generate(mut self) -> Result<Map, GenerateError> {
+        // First build the connection matrix
+        for ((x, y), connection) in self.find_connections() {
+            self.map
+                .get_section_mut(x, y)
+                .unwrap()
+                .connections
+                .push(connection);
+        }
+        // Go through and mark all section as the same section
+        self.connect_sections();
+        // Rooms with sockets are opened to everything in front of their sockets
+        self.open_sockets();
+        // Open some of the unused connections to create loops
+        self.add_loops();
         // Prune corridor tree
-        for root_node in self.map.corridor_tree.clone() {
-            self.iterate_node(&root_node, 100);
+        match self.dead_end_removal {
+            DeadEndRemoval::Short => {
+                for root_node in self.map.corridor_tree.clone() {
+                    self.iterate_node(&root_node, 100);
+                }
+            }
+            removal => remove_dead_ends(&mut self.map, self.corridor_size, removal),
         }
-        return self.map;
+        place_doors(
+            self.rng,
+            &mut self.map,
+            &self.opened_connections,
+            self.door_width,
+            &self.door_kinds,
+        );
+        // Reconnect or throw away the sections that can't be reached,
+        // the first section is the one every other section was merged into
+        let main_id = self.map.section_vec[0].get_id();
+        ensure_connected(
+            &mut self.map,
+            main_id,
+            (self.corridor_size.0 as u32, self.corridor_size.1 as u32),
+            self.disconnected_policy,
+        )?;
+        // Hide some of the connections that are left
+        self.add_secret_passages();
+        return Ok(self.map);
     }
 
     fn connect_sections(&mut self) -> usize {
         let first_section = &self.map.section_vec[0];
         let id = first_section.get_id();
-        let connections = self.map.get_best_connections(&first_section);
-        let mut counted_connections = self.iterate_connections(&connections, id);
+        let connections = self.map.get_best_connections(first_section);
+        let mut counted_connections = self.iterate_connections(connections, id);
 
         // If the first section couldn't connect enough
         // then select the next id until we've found the best section
@@ -195,9 +312,9 @@ impl SectionMerger {
                 .iter()
                 .find(|section| Some(section.get_id()) > prev_id);
             if let Some(next_section) = option_next_section {
-                let next_connections = self.map.get_best_connections(&next_section);
+                let next_connections = self.map.get_best_connections(next_section);
                 prev_id = Some(next_section.get_id());
-                counted_connections = self.iterate_connections(&next_connections, id);
+                counted_connections = self.iterate_connections(next_connections, id);
                 if counted_connections > top_section.1 {
                     top_section = (prev_id.unwrap(), counted_connections);
                 }
@@ -206,61 +323,244 @@ impl SectionMerger {
                 prev_id = None;
             }
         }
-        // Check for unconnected sections
-        let mut unconnected_vec = vec![];
-        for section in &self.map.section_vec {
-            if section.get_id() != id {
-                unconnected_vec.push(section.clone());
-            }
-        }
         // Returns the ID of the best section
         return top_section.0;
     }
 
     /**
-     * Returns how many connections have been connected
+     * Opens the connections and the best connections of every section they
+     * reach, depth first. Returns how many connections have been connected
      */
-    fn iterate_connections(&mut self, connections: &Vec<Connection>, id: usize) -> u32 {
+    fn iterate_connections(&mut self, connections: Vec<Connection>, id: usize) -> u32 {
         let mut counted_connections = 0;
-        for connection in connections {
-            // if the connection is from a corridor to
-            // another corridor
-            match connection.direction {
-                Direction::N | Direction::S => {
-                    self.map.set_rect(
-                        Cell::Connection,
-                        connection.x,
-                        connection.y,
-                        self.corridor_size.0 as u16,
-                        self.margins.1 as u16,
-                    );
-                }
-                Direction::W | Direction::E => {
-                    self.map.set_rect(
-                        Cell::Connection,
-                        connection.x,
-                        connection.y,
-                        self.margins.0 as u16,
-                        self.corridor_size.1 as u16,
-                    );
+        // The connections that are left for every section on the way
+        let mut stack = vec![connections.into_iter()];
+        while let Some(pending) = stack.last_mut() {
+            let connection = match pending.next() {
+                Some(connection) => connection,
+                None => {
+                    stack.pop();
+                    continue;
                 }
-            }
+            };
+            self.open_connection(&connection);
             if self.map.get_connection_section(&connection).get_id() != id {
                 counted_connections += 1;
                 self.map.get_connection_section_mut(&connection).set_id(id);
                 let connections = self
                     .map
-                    .get_best_connections(self.map.get_connection_section(connection));
-                counted_connections += self.iterate_connections(&connections, id);
+                    .get_best_connections(self.map.get_connection_section(&connection));
+                stack.push(connections.into_iter());
             }
         }
         return counted_connections;
     }
 
+    fn open_connection(&mut self, connection: &Connection) {
+        let (width, height) = self.connection_size(connection);
+        self.map
+            .set_rect(Cell::Connection, connection.x, connection.y, width, height);
+        self.opened_connections.push((*connection, (width, height)));
+    }
+
+    fn connection_size(&self, connection: &Connection) -> (u32, u32) {
+        match connection.direction {
+            Direction::N | Direction::S => {
+                return (self.corridor_size.0 as u32, self.margins.1 as u32);
+            }
+            Direction::W | Direction::E => {
+                return (self.margins.0 as u32, self.corridor_size.1 as u32);
+            }
+        }
+    }
+
+    /**
+     * Opens unused connections by chance to create loops in the dungeon.
+     * A group of connections is only opened if the walk between its two
+     * sides is at least `loop_min_distance` cells long
+     */
+    fn add_loops(&mut self) {
+        if self.extra_connection_chance <= 0f32 {
+            return;
+        }
+        let mut groups = self.unused_connection_groups();
+        groups.shuffle(self.rng);
+
+        for group in groups {
+            if self.rng.gen::<f32>() >= self.extra_connection_chance {
+                continue;
+            }
+            let best = best_connection(&group);
+            if self.is_unused(&best) && self.is_long_loop(&best) {
+                self.open_connection(&best);
+            }
+        }
+    }
+
+    /**
+     * Opens the best connection of every room with sockets to each section
+     * next to it, so the corridors dug in front of the sockets are joined to
+     * the room even when the maze never reaches them
+     */
+    fn open_sockets(&mut self) {
+        let sections: Vec<usize> = self
+            .map
+            .iter_rooms()
+            .filter(|room| room.has_sockets())
+            .map(|room| room.section_id)
+            .collect();
+        for section in sections {
+            // Sorted so the best connection comes first for every neighbour
+            let mut connections = self.map.section_vec[section].connections.clone();
+            connections.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+            let mut neighbours = vec![];
+            for connection in connections {
+                let neighbour = (connection.id, connection.direction);
+                if !neighbours.contains(&neighbour) {
+                    neighbours.push(neighbour);
+                    if self.is_unused(&connection) {
+                        self.open_connection(&connection);
+                    }
+                }
+            }
+        }
+    }
+
+    /**
+     * Turns a share of the unused connections that would make a long
+     * enough loop into secret passages
+     */
+    fn add_secret_passages(&mut self) {
+        if self.secret_passage_share <= 0f32 {
+            return;
+        }
+        let mut candidates: Vec<Connection> = self
+            .unused_connection_groups()
+            .iter()
+            .map(|group| best_connection(group))
+            .filter(|connection| self.is_long_loop(connection))
+            .collect();
+        candidates.shuffle(self.rng);
+        let count =
+            (candidates.len() as f32 * self.secret_passage_share.min(1f32)).round() as usize;
+
+        for best in candidates.into_iter().take(count) {
+            // Passages next to each other can overlap
+            if self.is_unused(&best) {
+                let (width, height) = self.connection_size(&best);
+                self.map
+                    .set_rect(Cell::SecretPassage, best.x, best.y, width, height);
+                self.map.secret_connections.push(best);
+            }
+        }
+    }
+
+    /**
+     * Every connection that hasn't been opened, neighbouring connections
+     * along the same wall are grouped together
+     */
+    fn unused_connection_groups(&self) -> Vec<Vec<Connection>> {
+        // Every connection is stored in both sections, only keep one of them
+        let mut candidates: Vec<Connection> = self
+            .map
+            .section_vec
+            .iter()
+            .flat_map(|section| section.get_connections().iter())
+            .filter(|c| c.direction == Direction::E || c.direction == Direction::S)
+            .filter(|c| self.is_unused(c))
+            .cloned()
+            .collect();
+        // Sort the connections along each wall so that groups are next to each other
+        candidates.sort_by_key(|c| match c.direction {
+            Direction::E => (c.id, 0, c.x, c.y),
+            _ => (c.id, 1, c.y, c.x),
+        });
+        let mut groups: Vec<Vec<Connection>> = vec![];
+        for candidate in candidates {
+            let continues_group = match groups.last().and_then(|group| group.last()) {
+                Some(last) => {
+                    last.id == candidate.id
+                        && last.direction == candidate.direction
+                        && match candidate.direction {
+                            Direction::E => last.x == candidate.x && last.y + 1 == candidate.y,
+                            _ => last.y == candidate.y && last.x + 1 == candidate.x,
+                        }
+                }
+                None => false,
+            };
+            if continues_group {
+                groups.last_mut().unwrap().push(candidate);
+            } else {
+                groups.push(vec![candidate]);
+            }
+        }
+        return groups;
+    }
+
+    /**
+     * Returns true if nothing has been carved where the connection is
+     */
+    fn is_unused(&self, connection: &Connection) -> bool {
+        let (width, height) = self.connection_size(connection);
+        return self
+            .map
+            .rect_is(
+                connection.x as i32,
+                connection.y as i32,
+                width,
+                height,
+                |c| !c.is_rock(),
+            )
+            .is_none();
+    }
+
+    /**
+     * Returns true if the walk between the two sides of the connection
+     * is at least `loop_min_distance` cells long
+     */
+    fn is_long_loop(&self, connection: &Connection) -> bool {
+        let (width, height) = self.connection_size(connection);
+        let (x, y) = (connection.x as i32, connection.y as i32);
+        let (from, to) = match connection.direction {
+            Direction::E | Direction::W => ((x - 1, y), (x + width as i32, y)),
+            Direction::N | Direction::S => ((x, y - 1), (x, y + height as i32)),
+        };
+        if !self.map.get(from.0, from.1).is_walkable() || !self.map.get(to.0, to.1).is_walkable() {
+            return false;
+        }
+        return !self.is_within_walk(from, to, self.loop_min_distance.saturating_sub(1));
+    }
+
+    /**
+     * Returns true if there is a walk of at most `max_distance` steps
+     * between two cells, only the cells that close are searched
+     */
+    fn is_within_walk(&self, from: (i32, i32), to: (i32, i32), max_distance: u32) -> bool {
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        visited.insert(from);
+        queue.push_back((from, 0));
+        while let Some(((x, y), distance)) = queue.pop_front() {
+            if (x, y) == to {
+                return true;
+            }
+            if distance == max_distance {
+                continue;
+            }
+            for neighbour in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)].iter() {
+                if self.map.get(neighbour.0, neighbour.1).is_walkable()
+                    && visited.insert(*neighbour)
+                {
+                    queue.push_back((*neighbour, distance + 1));
+                }
+            }
+        }
+        return false;
+    }
+
     fn score_pos(&self, x: i32, y: i32, horizontal: bool) -> f32 {
-        let position = if horizontal { x } else { y };
         return match self.map.get(x, y) {
-            Cell::Room(idx) => self.score_room_pos(&self.map.get_room(*idx), position, horizontal),
+            Cell::Room(idx) => self.score_room_pos(self.map.get_room(*idx), x, y, horizontal),
             Cell::Corridor(_) => 1f32,
             _ => 0f32,
         };
@@ -270,8 +570,8 @@ impl SectionMerger {
         match self.map.rect_is(
             x,
             y,
-            self.corridor_size.0 as u16,
-            self.corridor_size.1 as u16,
+            self.corridor_size.0 as u32,
+            self.corridor_size.1 as u32,
             |c| self.map.get_cell_section(c) != Some(section),
         ) {
             Some(_) => return false,
@@ -280,39 +580,92 @@ impl SectionMerger {
         return true;
     }
 
-    fn score_room_pos(&self, room: &Room, position: i32, horizontal: bool) -> f32 {
-        if horizontal {
-            (1f32
-                - (position as f32 + (self.corridor_size.0 as f32 / 2f32)
-                    - (room.x as f32 + (room.width as f32 / 2f32)))
-                    .abs()
-                    / (room.width as f32 / 2f32))
-                .max(0f32)
-        } else {
-            (1f32
-                - (position as f32 + (self.corridor_size.1 as f32 / 2f32)
-                    - (room.y as f32 + (room.height as f32 / 2f32)))
-                    .abs()
-                    / (room.height as f32 / 2f32))
-                .max(0f32)
+    /**
+     * Rooms with sockets are only connected through them, one of the room
+     * cells along the side of the connection has to be a socket
+     */
+    fn is_socket_side(&self, x: i32, y: i32, width: u8, height: u8) -> bool {
+        let room = match self.map.get(x, y) {
+            Cell::Room(idx) => self.map.get_room(*idx),
+            _ => return true,
+        };
+        if !room.has_sockets() {
+            return true;
+        }
+        for pos_y in y..y + height as i32 {
+            for pos_x in x..x + width as i32 {
+                if room.is_socket(pos_x, pos_y) {
+                    return true;
+                }
+            }
         }
+        return false;
     }
 
-    fn iterate_node(&mut self, node: &WrappedCorridorNode, count: u32) {
-        let children = &node.borrow().children.clone();
+    /**
+     * Connections can't be carved through the solid rock outside of the map shape
+     */
+    fn is_carvable(&self, x: i32, y: i32, width: u32, height: u32) -> bool {
+        return self
+            .map
+            .rect_is(x, y, width, height, |c| *c == Cell::SolidRock)
+            .is_none();
+    }
 
-        if node.borrow().children.len() > 1 {
-            // There's a branching in the tree
-            // Mark this as a branch.
-            for child in children {
-                self.iterate_node(&child, 0);
+    /**
+     * Scores how close to the middle of the room footprint the position is,
+     * measured along the row or column of the room that it connects to
+     */
+    fn score_room_pos(&self, room: &Room, x: i32, y: i32, horizontal: bool) -> f32 {
+        let (span, position, corridor_size) = if horizontal {
+            (room.row_span(y), x, self.corridor_size.0)
+        } else {
+            (room.column_span(x), y, self.corridor_size.1)
+        };
+        match span {
+            Some((start, end)) => {
+                let half_size = (end - start) as f32 / 2f32;
+                return (1f32
+                    - (position as f32 + (corridor_size as f32 / 2f32)
+                        - (start as f32 + half_size))
+                        .abs()
+                        / half_size)
+                    .max(0f32);
             }
+            None => return 0f32,
         }
-        if node.borrow().children.len() == 1 {
-            // it's a continuation of the branch
-            self.iterate_node(&children[0], count + 1)
+    }
+
+    /**
+     * Walks the corridor tree and removes the leaves at the end of short
+     * branches, children are visited before their parent so whole branches
+     * are pruned from the tip
+     */
+    fn iterate_node(&mut self, node: &WrappedCorridorNode, count: u32) {
+        // (node, pieces since the last branching, children visited)
+        let mut stack = vec![(Rc::clone(node), count, false)];
+        while let Some((node, count, visited)) = stack.pop() {
+            if visited {
+                self.prune_leaf(&node, count);
+                continue;
+            }
+            let children = node.borrow().children.clone();
+            stack.push((Rc::clone(&node), count, true));
+            if children.len() > 1 {
+                // There's a branching in the tree
+                // Mark this as a branch.
+                for child in children.iter().rev() {
+                    stack.push((Rc::clone(child), 0, false));
+                }
+            } else if children.len() == 1 {
+                // it's a continuation of the branch
+                stack.push((Rc::clone(&children[0]), count + 1, false));
+            }
         }
-        if node.borrow().children.len() == 0 {
+    }
+
+    fn prune_leaf(&mut self, node: &WrappedCorridorNode, count: u32) {
+        if node.borrow().children.is_empty() {
             // it's a leaf
             if count < self.prune_length {
                 // Check if there's any connections surrounding it
@@ -325,10 +678,10 @@ impl SectionMerger {
                         (borrowed_node.x as i32 - 1, (borrowed_node.y) as i32),
                         (
                             (borrowed_node.x) as i32,
-                            (borrowed_node.y + self.corridor_size.1 as u16) as i32,
+                            (borrowed_node.y + self.corridor_size.1 as u32) as i32,
                         ),
                         (
-                            (borrowed_node.x + self.corridor_size.0 as u16) as i32,
+                            (borrowed_node.x + self.corridor_size.0 as u32) as i32,
                             borrowed_node.y as i32,
                         ),
                     ],
@@ -340,7 +693,7 @@ impl SectionMerger {
                     Some(_) => {}
                     _ => {
                         // There is no connection surrounding it
-                        match get_parent(&node) {
+                        match get_parent(node) {
                             Some(parent) => {
                                 match (
                                     parent.borrow().x as i32 - (node.borrow().x as i32),
@@ -349,40 +702,40 @@ impl SectionMerger {
                                     (x, _) if (x < 0) => {
                                         // parent is to the left
                                         self.map.set_rect(
-                                            Cell::Removed, // Wall
-                                            node.borrow().x + 1,
+                                            Cell::Rock,
+                                            node.borrow().x + self.corridor_size.0 as u32 - 1,
                                             node.borrow().y,
                                             1,
-                                            self.corridor_size.1 as u16,
+                                            self.corridor_size.1 as u32,
                                         );
                                     }
                                     (x, _) if (x > 0) => {
                                         // parent is to the right
                                         self.map.set_rect(
-                                            Cell::Removed, // Wall
+                                            Cell::Rock,
                                             node.borrow().x,
                                             node.borrow().y,
                                             1,
-                                            self.corridor_size.1 as u16,
+                                            self.corridor_size.1 as u32,
                                         );
                                     }
                                     (_, y) if (y < 0) => {
                                         // parent is to the top
                                         self.map.set_rect(
-                                            Cell::Removed, // Wall
+                                            Cell::Rock,
                                             node.borrow().x,
-                                            node.borrow().y + 1,
-                                            self.corridor_size.0 as u16,
+                                            node.borrow().y + self.corridor_size.1 as u32 - 1,
+                                            self.corridor_size.0 as u32,
                                             1,
                                         );
                                     }
                                     _ => {
                                         // parent is at the bottom
                                         self.map.set_rect(
-                                            Cell::Removed, // Wall
+                                            Cell::Rock,
                                             node.borrow().x,
                                             node.borrow().y,
-                                            self.corridor_size.0 as u16,
+                                            self.corridor_size.0 as u32,
                                             1,
                                         );
                                     }
@@ -397,3 +750,72 @@ impl SectionMerger {
         }
     }
 }
+
+fn best_connection(group: &[Connection]) -> Connection {
+    return *group
+        .iter()
+        .max_by(|a, b| a.score.partial_cmp(&b.score).unwrap())
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cell_matrix::Cell;
+    use crate::map_generator::Generator;
+
+    /**
+     * Walkable cells between the rooms and corridors
+     */
+    fn opened_cells(chance: f32, distance: u32, seed: u64) -> usize {
+        let map = Generator::new()
+            .seed(seed)
+            .extra_connection_chance(chance)
+            .loop_min_distance(distance)
+            .generate();
+        return map
+            .iter()
+            .filter(|(_, _, cell)| cell.is_walkable() && !cell.is_room() && !cell.is_corridor())
+            .count();
+    }
+
+    #[test]
+    fn extra_connections_open_loops() {
+        for seed in 0..5 {
+            assert!(opened_cells(1f32, 1, seed) > opened_cells(0f32, 1, seed));
+        }
+    }
+
+    #[test]
+    fn short_loops_stay_closed() {
+        let far: usize = (0..5).map(|seed| opened_cells(1f32, 100_000, seed)).sum();
+        let near: usize = (0..5).map(|seed| opened_cells(1f32, 1, seed)).sum();
+        assert!(far < near);
+    }
+
+    #[test]
+    fn secret_passages_are_on_the_map() {
+        let mut passages = 0;
+        for seed in 0..5 {
+            let map = Generator::new()
+                .seed(seed)
+                .secret_passage_share(1f32)
+                .loop_min_distance(1)
+                .generate();
+            passages += map.secret_connections.len();
+            for connection in &map.secret_connections {
+                let cell = map.get(connection.x as i32, connection.y as i32);
+                assert!(*cell == Cell::SecretPassage, "{}", connection);
+            }
+        }
+        assert!(passages > 0);
+    }
+
+    #[test]
+    fn no_share_no_secret_passages() {
+        for seed in 0..5 {
+            let map = Generator::new().seed(seed).generate();
+            assert!(map.secret_connections.is_empty());
+            assert!(map.iter().all(|(_, _, cell)| *cell != Cell::SecretPassage));
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use crate::cell_matrix::Map;
+use crate::map_generator::GeneratorOptions;
+use crate::prefab::roll_prefab;
+use crate::room::Room;
+use crate::room_generator::{add_room, room_fits, room_limit_reached, room_size};
+
+use rand::Rng;
+
+const PI_2: f32 = std::f32::consts::PI * 2f32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Separation {
+    // How many rooms are placed, fewer fit when the map is too small for them
+    pub rooms: u32,
+    // 0-1, how far from the center of the map the rooms start, relative to its size
+    pub spread: f32,
+    // How many times the overlapping rooms are pushed apart at most
+    pub steps: u32,
+}
+
+impl Default for Separation {
+    fn default() -> Self {
+        return Separation {
+            rooms: 12,
+            spread: 0.2,
+            steps: 500,
+        };
+    }
+}
+
+// (x, y, width, height), x and y are the top left of the room
+type Rect = (f32, f32, f32, f32);
+
+/**
+ * Scatters rooms around the center of the map and pushes the overlapping
+ * ones apart until they are the margins away from each other. The rooms
+ * are snapped to the grid and pulled back together after
+ */
+pub fn separate<R: Rng>(
+    rng: &mut R,
+    map: &mut Map,
+    separation: &Separation,
+    options: &GeneratorOptions,
+) {
+    let (map_width, map_height) = (map.width as f32, map.height as f32);
+    let margins = (options.margins.0 as f32, options.margins.1 as f32);
+    // One more cell than the margins so snapping can't bring rooms too close
+    let gap = (margins.0 + 1f32, margins.1 + 1f32);
+    // Prefab rooms keep their template until they are put down
+    let mut prefabs: Vec<Option<Room>> = vec![];
+    let mut rects: Vec<Rect> = (0..separation.rooms)
+        .map(|_| {
+            let prefab = roll_prefab(rng, options);
+            let (width, height) = match &prefab {
+                Some(room) => (room.width, room.height),
+                None => room_size(rng, options),
+            };
+            prefabs.push(prefab);
+            let (width, height) = (width as f32, height as f32);
+            let angle = rng.gen::<f32>() * PI_2;
+            // The square root spreads the rooms evenly over the circle
+            let distance = rng.gen::<f32>().sqrt() * separation.spread;
+            let center_x = map_width / 2f32 * (1f32 + distance * angle.cos());
+            let center_y = map_height / 2f32 * (1f32 + distance * angle.sin());
+            (
+                center_x - width / 2f32,
+                center_y - height / 2f32,
+                width,
+                height,
+            )
+        })
+        .collect();
+
+    for _ in 0..separation.steps {
+        if !push_apart(&mut rects, gap) {
+            break;
+        }
+    }
+    // Snapped to the grid with the center of the rooms on the center of the map
+    let mut rooms: Vec<(i32, i32, i32, i32)> = vec![];
+    if !rects.is_empty() {
+        let left = rects.iter().map(|r| r.0).fold(f32::MAX, f32::min);
+        let top = rects.iter().map(|r| r.1).fold(f32::MAX, f32::min);
+        let right = rects.iter().map(|r| r.0 + r.2).fold(f32::MIN, f32::max);
+        let bottom = rects.iter().map(|r| r.1 + r.3).fold(f32::MIN, f32::max);
+        let offset = (
+            (map_width - left - right) / 2f32,
+            (map_height - top - bottom) / 2f32,
+        );
+        rooms = rects
+            .iter()
+            .map(|(x, y, width, height)| {
+                (
+                    (x + offset.0).round() as i32,
+                    (y + offset.1).round() as i32,
+                    *width as i32,
+                    *height as i32,
+                )
+            })
+            .collect();
+    }
+    compact(
+        &mut rooms,
+        (options.margins.0 as i32, options.margins.1 as i32),
+        (map.width as i32, map.height as i32),
+        separation.steps,
+    );
+
+    // Rooms that were pushed outside of the map or its shape are moved to the
+    // closest free place after the others are put down
+    let room_shapes = &options.room_shapes;
+    let mut left_out = vec![];
+    for ((x, y, width, height), prefab) in rooms.into_iter().zip(prefabs) {
+        if room_limit_reached(map, options) {
+            return;
+        }
+        let (clamped_x, clamped_y) = (
+            x.max(0).min(map.width as i32 - width).max(0) as u32,
+            y.max(0).min(map.height as i32 - height).max(0) as u32,
+        );
+        let room = match prefab {
+            Some(mut room) => {
+                room.x = clamped_x;
+                room.y = clamped_y;
+                room
+            }
+            None => {
+                let room_shape = room_shapes[rng.gen_range(0, room_shapes.len())];
+                Room::new(
+                    rng,
+                    clamped_x,
+                    clamped_y,
+                    width as u32,
+                    height as u32,
+                    room_shape,
+                    0,
+                )
+            }
+        };
+        if x >= 0 && y >= 0 && room_fits(map, &room, options) {
+            add_room(map, room, options);
+        } else {
+            left_out.push(room);
+        }
+    }
+    for mut room in left_out {
+        if room_limit_reached(map, options) {
+            return;
+        }
+        if move_to_free_place(map, &mut room, options) {
+            add_room(map, room, options);
+        }
+    }
+}
+
+/**
+ * Searches for the closest position the room fits at in rings around it,
+ * returns false when the map is full
+ */
+fn move_to_free_place(map: &Map, room: &mut Room, options: &GeneratorOptions) -> bool {
+    let (start_x, start_y) = (room.x as i32, room.y as i32);
+    let max_x = map.width as i32 - room.width as i32;
+    let max_y = map.height as i32 - room.height as i32;
+    for distance in 0..=map.width.max(map.height) as i32 {
+        let mut ring = vec![];
+        for d_x in -distance..=distance {
+            ring.push((d_x, -distance));
+            ring.push((d_x, distance));
+        }
+        for d_y in (1 - distance)..distance {
+            ring.push((-distance, d_y));
+            ring.push((distance, d_y));
+        }
+        for (d_x, d_y) in ring {
+            let (x, y) = (start_x + d_x, start_y + d_y);
+            if x < 0 || y < 0 || x > max_x || y > max_y {
+                continue;
+            }
+            room.x = x as u32;
+            room.y = y as u32;
+            if room_fits(map, room, options) {
+                return true;
+            }
+        }
+    }
+    return false;
+}
+
+/**
+ * Pulls the rooms towards the center of the map a cell at a time, as long
+ * as they stay the margins away from the other rooms. This closes the holes
+ * pushing rooms apart leaves behind
+ */
+fn compact(
+    rooms: &mut [(i32, i32, i32, i32)],
+    margins: (i32, i32),
+    map_size: (i32, i32),
+    steps: u32,
+) {
+    for _ in 0..steps {
+        let mut moved = false;
+        for idx in 0..rooms.len() {
+            let (x, y, width, height) = rooms[idx];
+            // Doubled so the centers are whole numbers, rooms stop once
+            // they are less than a cell away from the center
+            let (d_x, d_y) = (map_size.0 - (2 * x + width), map_size.1 - (2 * y + height));
+            let steps = [
+                (if d_x.abs() >= 2 { d_x.signum() } else { 0 }, 0),
+                (0, if d_y.abs() >= 2 { d_y.signum() } else { 0 }),
+            ];
+            for (step_x, step_y) in steps.iter() {
+                if *step_x == 0 && *step_y == 0 {
+                    continue;
+                }
+                let (x, y, width, height) = rooms[idx];
+                let rect = (x + step_x, y + step_y, width, height);
+                let collides = rooms.iter().enumerate().any(|(other, room)| {
+                    other != idx
+                        && rect.0 < room.0 + room.2 + margins.0
+                        && room.0 < rect.0 + rect.2 + margins.0
+                        && rect.1 < room.1 + room.3 + margins.1
+                        && room.1 < rect.1 + rect.3 + margins.1
+                });
+                if !collides {
+                    rooms[idx] = rect;
+                    moved = true;
+                }
+            }
+        }
+        if !moved {
+            break;
+        }
+    }
+}
+
+/**
+ * Moves every pair of rooms that are closer than the gap apart along the
+ * axis they overlap the least on, returns whether any room was moved.
+ * The rooms are swept from left to right so only rooms that can overlap
+ * are compared
+ */
+fn push_apart(rects: &mut [Rect], gap: (f32, f32)) -> bool {
+    let mut order: Vec<usize> = (0..rects.len()).collect();
+    order.sort_by(|a, b| rects[*a].0.partial_cmp(&rects[*b].0).unwrap());
+    let mut moved = false;
+    for (i, a) in order.iter().enumerate() {
+        for b in order[i + 1..].iter() {
+            let (first, second) = (rects[*a], rects[*b]);
+            if second.0 >= first.0 + first.2 + gap.0 {
+                break;
+            }
+            let overlap_x =
+                (first.0 + first.2 + gap.0 - second.0).min(second.0 + second.2 + gap.0 - first.0);
+            let overlap_y =
+                (first.1 + first.3 + gap.1 - second.1).min(second.1 + second.3 + gap.1 - first.1);
+            // The extra cell in the gap leaves room for rounding errors
+            if overlap_x <= 0.01 || overlap_y <= 0.01 {
+                continue;
+            }
+            moved = true;
+            // Both rooms move half of the way, away from each other
+            if overlap_x < overlap_y {
+                let push = if first.0 + first.2 / 2f32 <= second.0 + second.2 / 2f32 {
+                    overlap_x / 2f32
+                } else {
+                    -overlap_x / 2f32
+                };
+                rects[*a].0 -= push;
+                rects[*b].0 += push;
+            } else {
+                let push = if first.1 + first.3 / 2f32 <= second.1 + second.3 / 2f32 {
+                    overlap_y / 2f32
+                } else {
+                    -overlap_y / 2f32
+                };
+                rects[*a].1 -= push;
+                rects[*b].1 += push;
+            }
+        }
+    }
+    return moved;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell_matrix::Cell;
+    use crate::map_generator::Generator;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn rooms_are_the_margins_apart() {
+        let separation = Separation::default();
+        let options = Generator::new().size(100, 100).margins(2, 3).options;
+        for seed in 0..10 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut map = Map::new(100, 100, Cell::Rock);
+            separate(&mut rng, &mut map, &separation, &options);
+            let rooms: Vec<&Room> = map.iter_rooms().collect();
+            // There's enough space for every room
+            assert_eq!(rooms.len(), 12, "seed {}", seed);
+            for (idx, room) in rooms.iter().enumerate() {
+                for other in rooms.iter().skip(idx + 1) {
+                    let apart = room.x + room.width + 2 <= other.x
+                        || other.x + other.width + 2 <= room.x
+                        || room.y + room.height + 3 <= other.y
+                        || other.y + other.height + 3 <= room.y;
+                    assert!(apart, "seed {}", seed);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fewer_rooms_fit_a_small_map() {
+        let separation = Separation {
+            rooms: 40,
+            ..Separation::default()
+        };
+        let options = Generator::new().size(30, 30).options;
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut map = Map::new(30, 30, Cell::Rock);
+        separate(&mut rng, &mut map, &separation, &options);
+        let rooms = map.iter_rooms().len();
+        assert!(rooms > 0 && rooms < 40);
+    }
+}
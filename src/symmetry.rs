@@ -0,0 +1,407 @@
+use crate::cell_matrix::{Cell, Map};
+use crate::connectivity::{find_bridge, section_index};
+use crate::corridor_tree::transform_tree;
+use crate::direction::{Direction, Orientation};
+use crate::error::GenerateError;
+use crate::map_generator::GeneratorOptions;
+use crate::map_shape::MapShape;
+use crate::room::Corridor;
+use crate::sections::{Connection, Section};
+
+use std::collections::HashMap;
+
+/**
+ * Symmetric maps are generated in one part of the map which is then
+ * mirrored or rotated onto the rest of the map
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Symmetry {
+    None,
+    // The left half is mirrored onto the right half
+    Horizontal,
+    // The top half is mirrored onto the bottom half
+    Vertical,
+    // The top left quarter is mirrored onto the other quarters
+    Both,
+    // The left half is rotated half a turn onto the right half
+    Rotational2,
+    // The top left quarter is rotated a quarter turn at a time onto the
+    // other quarters, requires a square map
+    Rotational4,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Transform {
+    Identity,
+    FlipX,
+    FlipY,
+    // Rotations are clockwise
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl Transform {
+    fn apply(self, x: u32, y: u32, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            Transform::Identity => return (x, y),
+            Transform::FlipX => return (width - 1 - x, y),
+            Transform::FlipY => return (x, height - 1 - y),
+            Transform::Rotate90 => return (width - 1 - y, x),
+            Transform::Rotate180 => return (width - 1 - x, height - 1 - y),
+            Transform::Rotate270 => return (y, height - 1 - x),
+        }
+    }
+    /**
+     * Transforms a rectangle, returns (x, y, width, height)
+     */
+    fn apply_rect(
+        self,
+        x: u32,
+        y: u32,
+        rect_width: u32,
+        rect_height: u32,
+        width: u32,
+        height: u32,
+    ) -> (u32, u32, u32, u32) {
+        let corner = self.apply(x, y, width, height);
+        let opposite = self.apply(x + rect_width - 1, y + rect_height - 1, width, height);
+        return (
+            corner.0.min(opposite.0),
+            corner.1.min(opposite.1),
+            corner.0.max(opposite.0) - corner.0.min(opposite.0) + 1,
+            corner.1.max(opposite.1) - corner.1.min(opposite.1) + 1,
+        );
+    }
+    fn apply_direction(self, direction: Direction) -> Direction {
+        match (self, direction) {
+            (Transform::FlipX, Direction::E) => return Direction::W,
+            (Transform::FlipX, Direction::W) => return Direction::E,
+            (Transform::FlipY, Direction::N) => return Direction::S,
+            (Transform::FlipY, Direction::S) => return Direction::N,
+            (Transform::Rotate90, d) => return d.turn_clockwise(),
+            (Transform::Rotate180, d) => return d.turn_clockwise().turn_clockwise(),
+            (Transform::Rotate270, d) => {
+                return d.turn_clockwise().turn_clockwise().turn_clockwise()
+            }
+            (_, d) => return d,
+        }
+    }
+    fn apply_orientation(self, orientation: Orientation) -> Orientation {
+        match (self, orientation) {
+            (Transform::Rotate90, Orientation::Horizontal)
+            | (Transform::Rotate270, Orientation::Horizontal) => return Orientation::Vertical,
+            (Transform::Rotate90, Orientation::Vertical)
+            | (Transform::Rotate270, Orientation::Vertical) => return Orientation::Horizontal,
+            (_, o) => return o,
+        }
+    }
+}
+
+impl Symmetry {
+    /**
+     * Every transform that places a copy of the generated part,
+     * the first one is always the generated part itself
+     */
+    fn transforms(self) -> Vec<Transform> {
+        match self {
+            Symmetry::None => return vec![Transform::Identity],
+            Symmetry::Horizontal => return vec![Transform::Identity, Transform::FlipX],
+            Symmetry::Vertical => return vec![Transform::Identity, Transform::FlipY],
+            Symmetry::Both => {
+                return vec![
+                    Transform::Identity,
+                    Transform::FlipX,
+                    Transform::FlipY,
+                    Transform::Rotate180,
+                ]
+            }
+            Symmetry::Rotational2 => return vec![Transform::Identity, Transform::Rotate180],
+            Symmetry::Rotational4 => {
+                return vec![
+                    Transform::Identity,
+                    Transform::Rotate90,
+                    Transform::Rotate180,
+                    Transform::Rotate270,
+                ]
+            }
+        }
+    }
+    /**
+     * The copies that the generated part has to be bridged to, the bridges
+     * are copied as well which connects every copy with each other
+     */
+    fn bridges(self) -> Vec<usize> {
+        match self {
+            Symmetry::None => return vec![],
+            Symmetry::Both => return vec![1, 2],
+            _ => return vec![1],
+        }
+    }
+    /**
+     * The size of the part of the map that is generated before it's copied
+     */
+    pub fn domain_size(self, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            Symmetry::None => return (width, height),
+            Symmetry::Horizontal | Symmetry::Rotational2 => return (width / 2, height),
+            Symmetry::Vertical => return (width, height / 2),
+            Symmetry::Both | Symmetry::Rotational4 => return (width / 2, height / 2),
+        }
+    }
+    /**
+     * Applies the map shape so that a cell is only rock if every copy of it
+     * is inside of the shape, which keeps the mask symmetric
+     */
+    pub fn apply_shape(self, shape: &MapShape, map: &mut Map, width: u32, height: u32) {
+        let transforms = self.transforms();
+        for y in 0..map.height {
+            for x in 0..map.width {
+                let inside = transforms.iter().all(|transform| {
+                    let (t_x, t_y) = transform.apply(x, y, width, height);
+                    shape.contains(t_x, t_y, width, height)
+                });
+                map.set(x, y, if inside { Cell::Rock } else { Cell::SolidRock });
+            }
+        }
+    }
+    /**
+     * Copies the generated part onto the rest of the map and bridges the
+     * copies so that they stay connected. Returns an error if a copy
+     * can't be bridged
+     */
+    pub fn mirror(self, domain: Map, options: &GeneratorOptions) -> Result<Map, GenerateError> {
+        if self == Symmetry::None {
+            return Ok(domain);
+        }
+        let (width, height) = (options.width, options.height);
+        let corridor_size = (
+            options.corridor_width as u32,
+            options.corridor_height as u32,
+        );
+        let mut map = Map::new(width, height, Cell::SolidRock);
+        self.apply_shape(&options.shape, &mut map, width, height);
+
+        let section_count = domain.section_vec.len();
+        let room_count = domain.iter_rooms().count();
+        let corridor_count = domain.iter_corridors().count();
+        for (copy, transform) in self.transforms().into_iter().enumerate() {
+            let section_offset = copy * section_count;
+            for room in domain.iter_rooms() {
+                map.push_room(room.transformed(
+                    |x, y| transform.apply(x, y, width, height),
+                    room.section_id + section_offset,
+                ));
+            }
+            for corridor in domain.iter_corridors() {
+                map.push_corridor(Corridor {
+                    section_id: corridor.section_id + section_offset,
+                });
+            }
+            let transform_connection = |connection: &Connection| {
+                let (rect_width, rect_height) = match connection.direction {
+                    Direction::N | Direction::S => (corridor_size.0, options.margins.1 as u32),
+                    Direction::E | Direction::W => (options.margins.0 as u32, corridor_size.1),
+                };
+                let (x, y, _, _) = transform.apply_rect(
+                    connection.x,
+                    connection.y,
+                    rect_width,
+                    rect_height,
+                    width,
+                    height,
+                );
+                return Connection {
+                    x,
+                    y,
+                    id: connection.id + section_offset,
+                    score: connection.score,
+                    direction: transform.apply_direction(connection.direction),
+                };
+            };
+            for section in &domain.section_vec {
+                let mut new_section = Section::new(map.section_vec.len());
+                new_section.set_id(section.get_id() + section_offset);
+                new_section.connections = section
+                    .get_connections()
+                    .iter()
+                    .map(transform_connection)
+                    .collect();
+                map.section_vec.push(new_section);
+            }
+            for connection in &domain.secret_connections {
+                map.secret_connections
+                    .push(transform_connection(connection));
+            }
+            for root_node in &domain.corridor_tree {
+                map.corridor_tree.push(transform_tree(root_node, |x, y| {
+                    let (x, y, _, _) =
+                        transform.apply_rect(x, y, corridor_size.0, corridor_size.1, width, height);
+                    (x, y)
+                }));
+            }
+            for (x, y, cell) in domain.iter() {
+                let cell = match *cell {
+                    Cell::Room(idx) => Cell::Room(idx + copy * room_count),
+                    Cell::Corridor(idx) => Cell::Corridor(idx + copy * corridor_count),
+                    Cell::Door(orientation, kind) => {
+                        Cell::Door(transform.apply_orientation(orientation), kind)
+                    }
+                    cell => cell,
+                };
+                let (x, y) = transform.apply(x, y, width, height);
+                map.set(x, y, cell);
+            }
+        }
+        self.connect_copies(&mut map, section_count, corridor_size)?;
+        return Ok(map);
+    }
+
+    fn connect_copies(
+        self,
+        map: &mut Map,
+        section_count: usize,
+        corridor_size: (u32, u32),
+    ) -> Result<(), GenerateError> {
+        let transforms = self.transforms();
+        // The largest group of connected sections in the generated part
+        // is the one that gets bridged
+        let mut group_sizes: HashMap<usize, usize> = HashMap::new();
+        for cell in &map.cell_vector {
+            if let Some(idx) = section_index(map, cell) {
+                if idx < section_count {
+                    *group_sizes
+                        .entry(map.section_vec[idx].get_id())
+                        .or_insert(0) += 1;
+                }
+            }
+        }
+        let main_group = match group_sizes
+            .into_iter()
+            .max_by_key(|(id, size)| (*size, *id))
+        {
+            Some((id, _)) => id,
+            None => return Ok(()),
+        };
+
+        // Which copy each copy is connected to, copies connected to the
+        // first copy are merged into the same section group
+        let mut copy_groups: Vec<usize> = (0..transforms.len()).collect();
+        for target in self.bridges() {
+            let target_group = main_group + target * section_count;
+            let labels: Vec<u8> = map
+                .cell_vector
+                .iter()
+                .map(|cell| match section_index(map, cell) {
+                    Some(idx) if map.section_vec[idx].get_id() == main_group => 1,
+                    Some(idx) if map.section_vec[idx].get_id() == target_group => 2,
+                    _ => 0,
+                })
+                .collect();
+            let (path, start, end) = match find_bridge(map, &labels, corridor_size) {
+                Some(bridge) => bridge,
+                None => return Err(GenerateError::SymmetryDisconnected),
+            };
+            for transform in &transforms {
+                for (x, y) in &path {
+                    let (x, y, rect_width, rect_height) = transform.apply_rect(
+                        *x,
+                        *y,
+                        corridor_size.0,
+                        corridor_size.1,
+                        map.width,
+                        map.height,
+                    );
+                    for pos_y in y..(y + rect_height) {
+                        for pos_x in x..(x + rect_width) {
+                            if map.get(pos_x as i32, pos_y as i32).is_rock() {
+                                map.set(pos_x, pos_y, Cell::Connection);
+                            }
+                        }
+                    }
+                }
+                let start = transform.apply(start.0, start.1, map.width, map.height);
+                let end = transform.apply(end.0, end.1, map.width, map.height);
+                let start_copy = section_index(map, map.get(start.0 as i32, start.1 as i32));
+                let end_copy = section_index(map, map.get(end.0 as i32, end.1 as i32));
+                if let (Some(start_copy), Some(end_copy)) = (start_copy, end_copy) {
+                    let from = copy_groups[start_copy / section_count];
+                    let to = copy_groups[end_copy / section_count];
+                    for group in copy_groups.iter_mut() {
+                        if *group == to {
+                            *group = from;
+                        }
+                    }
+                }
+            }
+        }
+        for (idx, section) in map.section_vec.iter_mut().enumerate() {
+            let copy = idx / section_count;
+            if copy_groups[copy] == copy_groups[0]
+                && section.get_id() == main_group + copy * section_count
+            {
+                section.set_id(main_group);
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cave::regions;
+    use crate::map_generator::Generator;
+
+    use std::collections::HashSet;
+
+    /**
+     * Checks that the rooms are the same after the transform of a copy
+     * and that the copies are connected
+     */
+    fn assert_mirrored<F>(symmetry: Symmetry, size: (u32, u32), transform: F)
+    where
+        F: Fn((u32, u32, u32, u32)) -> (u32, u32, u32, u32),
+    {
+        for seed in 0..5 {
+            let map = Generator::new()
+                .seed(seed)
+                .size(size.0, size.1)
+                .symmetry(symmetry)
+                .generate();
+            let rooms: HashSet<(u32, u32, u32, u32)> = map
+                .iter_rooms()
+                .map(|room| (room.x, room.y, room.width, room.height))
+                .collect();
+            assert!(!rooms.is_empty());
+            for room in rooms.iter() {
+                assert!(rooms.contains(&transform(*room)), "{:?}", symmetry);
+            }
+            let rock: Vec<bool> = map.cell_vector.iter().map(|c| !c.is_walkable()).collect();
+            assert_eq!(regions(&rock, (size.0 as i32, size.1 as i32)).len(), 1);
+        }
+    }
+
+    #[test]
+    fn maps_are_mirrored() {
+        let (w, h) = (64, 32);
+        assert_mirrored(Symmetry::Horizontal, (w, h), |(x, y, width, height)| {
+            (w - x - width, y, width, height)
+        });
+        assert_mirrored(Symmetry::Vertical, (w, h), |(x, y, width, height)| {
+            (x, h - y - height, width, height)
+        });
+        assert_mirrored(Symmetry::Both, (w, h), |(x, y, width, height)| {
+            (w - x - width, y, width, height)
+        });
+        assert_mirrored(Symmetry::Both, (w, h), |(x, y, width, height)| {
+            (x, h - y - height, width, height)
+        });
+        assert_mirrored(Symmetry::Rotational2, (w, h), |(x, y, width, height)| {
+            (w - x - width, h - y - height, width, height)
+        });
+        assert_mirrored(Symmetry::Rotational4, (w, w), |(x, y, width, height)| {
+            (w - y - height, x, height, width)
+        });
+    }
+}
@@ -0,0 +1,176 @@
+use crate::cell_matrix::{Cell, Map};
+use crate::direction::Orientation;
+
+use std::collections::VecDeque;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Corner {
+    NorthEast,
+    SouthEast,
+    SouthWest,
+    NorthWest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WallCorner {
+    // A straight piece of wall
+    None,
+    // The floor only touches the wall diagonally, in the given corner
+    Outer(Corner),
+    // The floor touches the two sides of the wall that meet in the given corner
+    Inner(Corner),
+    // The floor is on opposite sides of the wall, or on three or more sides
+    Junction,
+}
+
+/**
+ * Turns rock next to walkable cells into walls, and rock within
+ * `perimeter` cells of a wall into `Cell::Perimeter` with the distance
+ * to the closest wall
+ */
+pub fn place_walls(map: &mut Map, perimeter: u8) {
+    let mut walls = vec![];
+    for y in 0..map.height as i32 {
+        for x in 0..map.width as i32 {
+            if !map.get(x, y).is_rock() {
+                continue;
+            }
+            let floor = |d_x: i32, d_y: i32| map.get(x + d_x, y + d_y).is_walkable();
+            let (n, e, s, w) = (floor(0, -1), floor(1, 0), floor(0, 1), floor(-1, 0));
+            let sides = [n, e, s, w].iter().filter(|side| **side).count();
+            let corner = match (n, e, s, w) {
+                (false, false, false, false) => {
+                    if floor(1, -1) {
+                        WallCorner::Outer(Corner::NorthEast)
+                    } else if floor(1, 1) {
+                        WallCorner::Outer(Corner::SouthEast)
+                    } else if floor(-1, 1) {
+                        WallCorner::Outer(Corner::SouthWest)
+                    } else if floor(-1, -1) {
+                        WallCorner::Outer(Corner::NorthWest)
+                    } else {
+                        continue;
+                    }
+                }
+                _ if sides >= 3 || (n && s) || (e && w) => WallCorner::Junction,
+                (true, true, _, _) => WallCorner::Inner(Corner::NorthEast),
+                (_, true, true, _) => WallCorner::Inner(Corner::SouthEast),
+                (_, _, true, true) => WallCorner::Inner(Corner::SouthWest),
+                (true, _, _, true) => WallCorner::Inner(Corner::NorthWest),
+                _ => WallCorner::None,
+            };
+            // Walls with floor only to the left or right run north to south
+            let orientation = if (e || w) && !(n || s) {
+                Orientation::Vertical
+            } else {
+                Orientation::Horizontal
+            };
+            walls.push((x as u32, y as u32, Cell::Wall(orientation, corner)));
+        }
+    }
+    let mut distances = vec![0u8; map.cell_vector.len()];
+    let mut queue = VecDeque::new();
+    for (x, y, wall) in walls {
+        map.set(x, y, wall);
+        queue.push_back((x as i32, y as i32));
+    }
+    if perimeter == 0 {
+        return;
+    }
+
+    // Walk outwards from the walls through the rock
+    let width = map.width as i32;
+    while let Some((x, y)) = queue.pop_front() {
+        let distance = distances[(y * width + x) as usize];
+        if distance >= perimeter {
+            continue;
+        }
+        for n_y in (y - 1)..=(y + 1) {
+            for n_x in (x - 1)..=(x + 1) {
+                if map.get(n_x, n_y).is_rock() {
+                    distances[(n_y * width + n_x) as usize] = distance + 1;
+                    map.set(n_x as u32, n_y as u32, Cell::Perimeter(distance + 1));
+                    queue.push_back((n_x, n_y));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_generator::Generator;
+
+    /**
+     * A 9 by 9 map with a 3 by 3 floor in the middle
+     */
+    fn walled_square(perimeter: u8) -> Map {
+        let mut map = Map::new(9, 9, Cell::Rock);
+        map.set_rect(Cell::Room(0), 3, 3, 3, 3);
+        place_walls(&mut map, perimeter);
+        return map;
+    }
+
+    #[test]
+    fn walls_follow_the_floor() {
+        let map = walled_square(0);
+        let straight = |orientation| Cell::Wall(orientation, WallCorner::None);
+        assert!(*map.get(4, 2) == straight(Orientation::Horizontal));
+        assert!(*map.get(4, 6) == straight(Orientation::Horizontal));
+        assert!(*map.get(2, 4) == straight(Orientation::Vertical));
+        assert!(*map.get(6, 4) == straight(Orientation::Vertical));
+        let outer = |corner| Cell::Wall(Orientation::Horizontal, WallCorner::Outer(corner));
+        assert!(*map.get(2, 2) == outer(Corner::SouthEast));
+        assert!(*map.get(6, 2) == outer(Corner::SouthWest));
+        assert!(*map.get(6, 6) == outer(Corner::NorthWest));
+        assert!(*map.get(2, 6) == outer(Corner::NorthEast));
+        assert!(*map.get(1, 4) == Cell::Rock);
+    }
+
+    #[test]
+    fn inner_corners_and_junctions() {
+        let mut map = Map::new(7, 7, Cell::Rock);
+        // An L of floor around (3, 3) and a wall between two floors at (3, 5)
+        map.set_rect(Cell::Room(0), 3, 1, 1, 2);
+        map.set_rect(Cell::Room(0), 4, 3, 2, 1);
+        map.set_rect(Cell::Room(0), 2, 5, 1, 1);
+        map.set_rect(Cell::Room(0), 4, 5, 1, 1);
+        place_walls(&mut map, 0);
+        assert!(
+            *map.get(3, 3)
+                == Cell::Wall(
+                    Orientation::Horizontal,
+                    WallCorner::Inner(Corner::NorthEast)
+                )
+        );
+        assert!(*map.get(3, 5) == Cell::Wall(Orientation::Vertical, WallCorner::Junction));
+    }
+
+    #[test]
+    fn perimeter_counts_the_distance_to_the_walls() {
+        let map = walled_square(2);
+        assert!(*map.get(1, 4) == Cell::Perimeter(1));
+        assert!(*map.get(0, 4) == Cell::Perimeter(2));
+        assert!(*map.get(1, 1) == Cell::Perimeter(1));
+        assert!(*map.get(0, 0) == Cell::Perimeter(2));
+    }
+
+    #[test]
+    fn generated_floor_is_walled_in() {
+        for seed in 0..10 {
+            let map = Generator::new()
+                .seed(seed)
+                .walls(true)
+                .perimeter(1)
+                .generate();
+            for (x, y, cell) in map.iter() {
+                if cell.is_walkable() {
+                    for (n_x, n_y, neighbor) in map.neighbors8(x, y) {
+                        assert!(!neighbor.is_rock(), "({}, {}) seed {}", n_x, n_y, seed);
+                    }
+                }
+            }
+        }
+    }
+}
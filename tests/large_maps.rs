@@ -0,0 +1,81 @@
+#![allow(clippy::needless_return)]
+
+extern crate daedalus;
+
+use daedalus::{Generator, Map};
+
+fn walkable_regions(map: &Map) -> usize {
+    let mut seen = vec![false; map.cell_vector.len()];
+    let mut regions = 0;
+    for (x, y, cell) in map.iter() {
+        let idx = (y * map.width + x) as usize;
+        if seen[idx] || !cell.is_walkable() {
+            continue;
+        }
+        regions += 1;
+        seen[idx] = true;
+        let mut stack = vec![(x, y)];
+        while let Some((x, y)) = stack.pop() {
+            for (n_x, n_y, cell) in map.neighbors4(x, y) {
+                let n_idx = (n_y * map.width + n_x) as usize;
+                if !seen[n_idx] && cell.is_walkable() {
+                    seen[n_idx] = true;
+                    stack.push((n_x, n_y));
+                }
+            }
+        }
+    }
+    return regions;
+}
+
+#[test]
+fn more_cells_than_u16() {
+    let map = Generator::new()
+        .size(300, 300)
+        .iterations(512)
+        .seed(3)
+        .generate();
+    assert_eq!(map.width, 300);
+    assert_eq!(map.height, 300);
+    assert_eq!(map.cell_vector.len(), 90_000);
+    assert_eq!(walkable_regions(&map), 1);
+}
+
+#[test]
+fn coordinates_larger_than_u16() {
+    let map = Generator::new()
+        .size(70_000, 16)
+        .iterations(512)
+        .seed(5)
+        .generate();
+    assert_eq!(map.width, 70_000);
+    assert_eq!(map.cell_vector.len(), 70_000 * 16);
+    // Something was carved past the old coordinate limit
+    assert!(map
+        .iter()
+        .any(|(x, _, cell)| x > u16::MAX as u32 && cell.is_walkable()));
+    assert_eq!(walkable_regions(&map), 1);
+}
+
+#[test]
+fn same_seed_same_large_map() {
+    let generator = || Generator::new().size(400, 250).seed(11);
+    assert_eq!(
+        generator().generate().to_string(),
+        generator().generate().to_string()
+    );
+}
+
+// Runs on the test thread, corridors used to be carved recursively which
+// overflowed its stack
+#[test]
+fn carve_huge_map_on_normal_stack() {
+    let map = Generator::new()
+        .size(2000, 2000)
+        .iterations(8192)
+        .corridor_errantness(0.1)
+        .seed(1)
+        .generate();
+    assert_eq!(map.cell_vector.len(), 4_000_000);
+    assert_eq!(walkable_regions(&map), 1);
+}
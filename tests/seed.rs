@@ -0,0 +1,22 @@
+#![allow(clippy::needless_return)]
+
+extern crate daedalus;
+
+use daedalus::Generator;
+
+#[test]
+fn same_seed_gives_same_map() {
+    let first = Generator::new().seed(1337).generate();
+    let second = Generator::new().seed(1337).generate();
+    assert_eq!(first.to_string(), second.to_string());
+    assert_eq!(first.seed, Some(1337));
+    assert_eq!(second.seed, Some(1337));
+}
+
+#[test]
+fn random_seed_is_stored() {
+    let map = Generator::new().generate();
+    let seed = map.seed.expect("generate stores the seed it used");
+    let again = Generator::new().seed(seed).generate();
+    assert_eq!(map.to_string(), again.to_string());
+}